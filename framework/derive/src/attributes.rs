@@ -0,0 +1,487 @@
+//! Declarative definitions of every attribute understood by `#[derive(Page)]`.
+//!
+//! Each attribute is a plain struct deriving [`FromMeta`], so adding a new option is a matter
+//! of adding a field. Unknown keys, duplicate keys and missing required keys are reported by
+//! darling with a span pointing at the offending tokens.
+
+use darling::{ast::NestedMeta, FromMeta};
+use syn::{
+    ext::IdentExt, parse::ParseStream, Attribute, Ident, LitStr, MacroDelimiter, Meta, MetaList,
+    MetaNameValue, Token,
+};
+
+/// `#[page(path = "/vms", refresh = "5s", state = "AppState", cache = "2s", cache_per_user)]`
+#[derive(Debug, FromMeta)]
+pub struct PageAttribute {
+    pub path: String,
+    #[darling(default)]
+    pub refresh: Option<LitStr>,
+    /// How long a constructed page is reused for other requests.
+    #[darling(default)]
+    pub cache: Option<LitStr>,
+    /// Caches the page separately for every user.
+    #[darling(default)]
+    pub cache_per_user: bool,
+    /// The state of the `FrameworkBuilder`, passed to actions as `Request<state>`.
+    #[darling(default)]
+    pub state: Option<syn::Type>,
+}
+
+/// `#[table]`, `#[table(selectable)]` or `#[table(export = ["csv", "xlsx"])]`
+#[derive(Debug, Default, FromMeta)]
+pub struct TableAttribute {
+    #[darling(default)]
+    pub selectable: bool,
+    /// The formats to offer download links for, in the order of the links.
+    #[darling(default)]
+    pub export: ExportFormats,
+}
+
+/// The formats of `#[table(export = [...])]`.
+#[derive(Debug, Default)]
+pub struct ExportFormats(pub Vec<ExportFormat>);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+    Xlsx,
+}
+
+impl ExportFormat {
+    /// The suffix of the page URL the format is served at.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+            Self::Xlsx => "xlsx",
+        }
+    }
+
+    /// The text of the download link.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Csv => "CSV",
+            Self::Ndjson => "NDJSON",
+            Self::Xlsx => "Excel",
+        }
+    }
+}
+
+impl FromMeta for ExportFormats {
+    fn from_expr(expr: &syn::Expr) -> darling::Result<Self> {
+        let syn::Expr::Array(array) = expr else {
+            return Err(darling::Error::custom(
+                "Expected a list of formats, e.g. export = [\"csv\", \"xlsx\"]",
+            )
+            .with_span(expr));
+        };
+        let mut formats = Vec::new();
+        for element in &array.elems {
+            let format = match element {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(str),
+                    ..
+                }) => match str.value().as_str() {
+                    "csv" => ExportFormat::Csv,
+                    "ndjson" => ExportFormat::Ndjson,
+                    "xlsx" => ExportFormat::Xlsx,
+                    other => {
+                        return Err(darling::Error::custom(format!(
+                            "Unknown export format {:?}, expected \"csv\", \"ndjson\" or \"xlsx\"",
+                            other
+                        ))
+                        .with_span(element))
+                    }
+                },
+                _ => return Err(darling::Error::custom("Expected a string").with_span(element)),
+            };
+            if formats.contains(&format) {
+                return Err(darling::Error::custom(format!(
+                    "Duplicate export format {:?}",
+                    format.extension()
+                ))
+                .with_span(element));
+            }
+            formats.push(format);
+        }
+        Ok(Self(formats))
+    }
+}
+
+/// `#[column(field = "name", header = "Name")]`
+#[derive(Debug, FromMeta)]
+pub struct ColumnAttribute {
+    pub field: String,
+    #[darling(default)]
+    pub header: Option<String>,
+}
+
+/// `#[action(name = "Turn on", fn = "turn_on", confirm = "Start {name}?", enabled_if = "is_off")]`
+/// or `#[bulk_action(name = "Stop", fn = "stop", confirm = "Stop {count} VMs?")]`
+#[derive(Debug, FromMeta)]
+pub struct ActionAttribute {
+    pub name: String,
+    #[darling(rename = "fn")]
+    pub function: String,
+    /// Asks for confirmation first, `{field}` is replaced with the value of the row's `field`
+    /// (or with the number of selected rows for `{count}` in a bulk action).
+    #[darling(default)]
+    pub confirm: Option<String>,
+    /// A `fn(&self) -> bool` method of the row type, the action is hidden if it returns false.
+    #[darling(default)]
+    pub enabled_if: Option<String>,
+}
+
+/// `#[component]` or `#[component(refresh = "10s")]`
+#[derive(Debug, Default, FromMeta)]
+pub struct ComponentAttribute {
+    #[darling(default)]
+    pub refresh: Option<LitStr>,
+}
+
+/// `#[label(text = "Uptime")]`
+#[derive(Debug, FromMeta)]
+pub struct LabelAttribute {
+    pub text: String,
+}
+
+/// Parses every `#[name(...)]` attribute into a `T`, in the order they are declared.
+///
+/// All errors are accumulated, so a single compile reports every malformed attribute.
+pub fn parse_all<T: FromMeta>(attributes: &[Attribute], name: &str) -> darling::Result<Vec<T>> {
+    let mut errors = darling::Error::accumulator();
+    let result = attributes
+        .iter()
+        .filter(|attribute| attribute.path().is_ident(name))
+        .filter_map(|attribute| errors.handle(parse(attribute)))
+        .collect();
+    errors.finish_with(result)
+}
+
+/// Parses a `#[name(...)]` attribute that may be declared at most once.
+pub fn parse_one<T: FromMeta>(attributes: &[Attribute], name: &str) -> darling::Result<Option<T>> {
+    let mut matching = attributes
+        .iter()
+        .filter(|attribute| attribute.path().is_ident(name));
+    let first = match matching.next() {
+        Some(first) => first,
+        None => return Ok(None),
+    };
+    if let Some(duplicate) = matching.next() {
+        return Err(
+            darling::Error::custom(format!("Duplicate #[{}] attribute", name)).with_span(duplicate),
+        );
+    }
+    parse(first).map(Some)
+}
+
+/// The `name` option of the `#[serde(...)]` attributes among `attributes`, e.g. `rename`.
+///
+/// Malformed serde attributes are skipped, serde's derive reports them.
+pub fn serde_option(attributes: &[Attribute], name: &str) -> Option<Meta> {
+    attributes
+        .iter()
+        .filter(|attribute| attribute.path().is_ident("serde"))
+        .filter_map(|attribute| attribute.parse_args_with(parse_nested_meta).ok())
+        .flatten()
+        .find_map(|nested| match nested {
+            NestedMeta::Meta(meta) if meta.path().is_ident(name) => Some(meta),
+            _ => None,
+        })
+}
+
+fn parse<T: FromMeta>(attribute: &Attribute) -> darling::Result<T> {
+    match &attribute.meta {
+        // A bare `#[table]` is treated as `#[table()]`, so flags can grow options later
+        Meta::Path(_) => T::from_list(&[]),
+        Meta::List(list) => T::from_list(&list.parse_args_with(parse_nested_meta)?),
+        meta => T::from_meta(meta),
+    }
+    .map_err(|e| e.with_span(attribute))
+}
+
+/// Same as darling's `NestedMeta::parse_meta_list`, but also accepts keywords as keys, so
+/// `#[action(fn = "turn_on")]` can be written.
+fn parse_nested_meta(input: ParseStream) -> syn::Result<Vec<NestedMeta>> {
+    let mut result = Vec::new();
+    while !input.is_empty() {
+        if input.peek(syn::Lit) {
+            result.push(NestedMeta::Lit(input.parse()?));
+        } else {
+            let path = syn::Path::from(Ident::parse_any(input)?);
+            let meta = if input.peek(Token![=]) {
+                Meta::NameValue(MetaNameValue {
+                    path,
+                    eq_token: input.parse()?,
+                    value: input.parse()?,
+                })
+            } else if input.peek(syn::token::Paren) {
+                let content;
+                let paren = syn::parenthesized!(content in input);
+                Meta::List(MetaList {
+                    path,
+                    delimiter: MacroDelimiter::Paren(paren),
+                    tokens: content.parse()?,
+                })
+            } else {
+                Meta::Path(path)
+            };
+            result.push(NestedMeta::Meta(meta));
+        }
+        if !input.is_empty() {
+            input.parse::<Token![,]>()?;
+        }
+    }
+    Ok(result)
+}
+
+#[test]
+fn test_parse_page() {
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[page(path = "/vms", refresh = "5s")])];
+    let page: PageAttribute = parse_one(&attrs, "page").unwrap().unwrap();
+    assert_eq!(page.path, "/vms");
+    assert_eq!(page.refresh.unwrap().value(), "5s");
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[page(path = "/uptime")])];
+    let page: PageAttribute = parse_one(&attrs, "page").unwrap().unwrap();
+    assert_eq!(page.path, "/uptime");
+    assert!(page.refresh.is_none());
+    assert!(page.state.is_none());
+
+    let attrs: Vec<Attribute> =
+        vec![syn::parse_quote!(#[page(path = "/vms", state = "crate::State")])];
+    let page: PageAttribute = parse_one(&attrs, "page").unwrap().unwrap();
+    let state = page.state.unwrap();
+    assert_eq!(quote::quote!(#state).to_string(), "crate :: State");
+}
+
+#[test]
+fn test_parse_page_errors() {
+    let missing: Vec<Attribute> = vec![syn::parse_quote!(#[page(refresh = "5s")])];
+    let err = parse_one::<PageAttribute>(&missing, "page").unwrap_err();
+    assert!(err.to_string().contains("path"), "{}", err);
+
+    let unknown: Vec<Attribute> = vec![syn::parse_quote!(#[page(path = "/", title = "x")])];
+    let err = parse_one::<PageAttribute>(&unknown, "page").unwrap_err();
+    assert!(err.to_string().contains("title"), "{}", err);
+
+    let duplicate_key: Vec<Attribute> = vec![syn::parse_quote!(#[page(path = "/", path = "/a")])];
+    assert!(parse_one::<PageAttribute>(&duplicate_key, "page").is_err());
+
+    let duplicate_attr: Vec<Attribute> = vec![
+        syn::parse_quote!(#[page(path = "/")]),
+        syn::parse_quote!(#[page(path = "/a")]),
+    ];
+    let err = parse_one::<PageAttribute>(&duplicate_attr, "page").unwrap_err();
+    assert!(err.to_string().contains("Duplicate"), "{}", err);
+
+    let wrong_type: Vec<Attribute> = vec![syn::parse_quote!(#[page(path = 5)])];
+    assert!(parse_one::<PageAttribute>(&wrong_type, "page").is_err());
+}
+
+#[test]
+fn test_parse_table_flag() {
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[table])];
+    assert!(parse_one::<TableAttribute>(&attrs, "table")
+        .unwrap()
+        .is_some());
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[table()])];
+    assert!(parse_one::<TableAttribute>(&attrs, "table")
+        .unwrap()
+        .is_some());
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[serde(skip)])];
+    assert!(parse_one::<TableAttribute>(&attrs, "table")
+        .unwrap()
+        .is_none());
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[table(selectable)])];
+    let table: TableAttribute = parse_one(&attrs, "table").unwrap().unwrap();
+    assert!(table.selectable);
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[table(sortable)])];
+    assert!(parse_one::<TableAttribute>(&attrs, "table").is_err());
+}
+
+#[test]
+fn test_parse_table_export() {
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[table(export = ["xlsx", "csv"])])];
+    let table: TableAttribute = parse_one(&attrs, "table").unwrap().unwrap();
+    assert_eq!(table.export.0, [ExportFormat::Xlsx, ExportFormat::Csv]);
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[table])];
+    let table: TableAttribute = parse_one(&attrs, "table").unwrap().unwrap();
+    assert!(table.export.0.is_empty());
+
+    for attr in [
+        syn::parse_quote!(#[table(export = "csv")]),
+        syn::parse_quote!(#[table(export = ["pdf"])]),
+        syn::parse_quote!(#[table(export = [csv])]),
+        syn::parse_quote!(#[table(export = ["csv", "csv"])]),
+    ] {
+        let attrs: Vec<Attribute> = vec![attr];
+        assert!(parse_one::<TableAttribute>(&attrs, "table").is_err());
+    }
+}
+
+#[test]
+fn test_parse_columns() {
+    let attrs: Vec<Attribute> = vec![
+        syn::parse_quote!(#[table]),
+        syn::parse_quote!(#[column(field = "name", header = "Name")]),
+        syn::parse_quote!(#[serde(rename = "machines")]),
+        syn::parse_quote!(#[column(field = "state")]),
+    ];
+    let columns: Vec<ColumnAttribute> = parse_all(&attrs, "column").unwrap();
+    assert_eq!(columns.len(), 2);
+    assert_eq!(columns[0].field, "name");
+    assert_eq!(columns[0].header.as_deref(), Some("Name"));
+    assert_eq!(columns[1].field, "state");
+    assert_eq!(columns[1].header, None);
+}
+
+#[test]
+fn test_parse_column_errors() {
+    let attrs: Vec<Attribute> = vec![
+        syn::parse_quote!(#[column(header = "Name")]),
+        syn::parse_quote!(#[column(field = "a", field = "b")]),
+        syn::parse_quote!(#[column(field = "a", width = 5)]),
+        syn::parse_quote!(#[column = "a"]),
+    ];
+    let err = parse_all::<ColumnAttribute>(&attrs, "column").unwrap_err();
+    assert_eq!(err.len(), 4, "{}", err);
+}
+
+#[test]
+fn test_parse_actions() {
+    let attrs: Vec<Attribute> = vec![
+        syn::parse_quote!(#[action(name = "on", fn = "turn_on")]),
+        syn::parse_quote!(#[action(fn = "turn_off", name = "off")]),
+    ];
+    let actions: Vec<ActionAttribute> = parse_all(&attrs, "action").unwrap();
+    assert_eq!(actions.len(), 2);
+    assert_eq!(actions[0].name, "on");
+    assert_eq!(actions[0].function, "turn_on");
+    assert_eq!(actions[1].name, "off");
+    assert_eq!(actions[1].function, "turn_off");
+
+    assert!(actions[0].confirm.is_none());
+    assert!(actions[0].enabled_if.is_none());
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[action(name = "on", action = "turn_on")])];
+    assert!(parse_all::<ActionAttribute>(&attrs, "action").is_err());
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[action(
+        name = "off",
+        fn = "turn_off",
+        confirm = "Really shut down {name}?",
+        enabled_if = "is_running"
+    )])];
+    let actions: Vec<ActionAttribute> = parse_all(&attrs, "action").unwrap();
+    assert_eq!(
+        actions[0].confirm.as_deref(),
+        Some("Really shut down {name}?")
+    );
+    assert_eq!(actions[0].enabled_if.as_deref(), Some("is_running"));
+}
+
+#[test]
+fn test_serde_option() {
+    let attrs: Vec<Attribute> = vec![
+        syn::parse_quote!(#[table]),
+        syn::parse_quote!(#[serde(default, with = "module")]),
+        syn::parse_quote!(#[serde(rename(serialize = "b"))]),
+    ];
+    let rename = serde_option(&attrs, "rename").unwrap();
+    assert!(matches!(rename, Meta::List(_)));
+    assert!(serde_option(&attrs, "default").is_some());
+    assert!(serde_option(&attrs, "flatten").is_none());
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[serde(rename = "b")])];
+    assert!(matches!(
+        serde_option(&attrs, "rename"),
+        Some(Meta::NameValue(_))
+    ));
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[table(rename = "b")])];
+    assert!(serde_option(&attrs, "rename").is_none());
+}
+
+#[test]
+fn test_parse_component() {
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[component])];
+    let component: ComponentAttribute = parse_one(&attrs, "component").unwrap().unwrap();
+    assert!(component.refresh.is_none());
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[component(refresh = "10s")])];
+    let component: ComponentAttribute = parse_one(&attrs, "component").unwrap().unwrap();
+    assert_eq!(component.refresh.unwrap().value(), "10s");
+}
+
+#[test]
+fn test_parse_label() {
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[label(text = "Uptime")])];
+    let label: LabelAttribute = parse_one(&attrs, "label").unwrap().unwrap();
+    assert_eq!(label.text, "Uptime");
+
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[label])];
+    assert!(parse_one::<LabelAttribute>(&attrs, "label").is_err());
+}
+
+/// Every option of every attribute is parsed back to the value it was written with.
+#[test]
+fn test_round_trip() {
+    let (path, refresh, cache) = ("/vms", "1m30s", "2s");
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[page(
+        path = #path,
+        refresh = #refresh,
+        cache = #cache,
+        cache_per_user,
+        state = "AppState"
+    )])];
+    let page: PageAttribute = parse_one(&attrs, "page").unwrap().unwrap();
+    assert_eq!(page.path, path);
+    assert_eq!(page.refresh.unwrap().value(), refresh);
+    assert_eq!(page.cache.unwrap().value(), cache);
+    assert!(page.cache_per_user);
+    let state = page.state.unwrap();
+    assert_eq!(quote::quote!(#state).to_string(), "AppState");
+
+    let attrs: Vec<Attribute> =
+        vec![syn::parse_quote!(#[table(selectable, export = ["ndjson", "csv", "xlsx"])])];
+    let table: TableAttribute = parse_one(&attrs, "table").unwrap().unwrap();
+    assert!(table.selectable);
+    let formats = [ExportFormat::Ndjson, ExportFormat::Csv, ExportFormat::Xlsx];
+    assert_eq!(table.export.0, formats);
+
+    let (field, header) = ("name", "Name");
+    let attrs: Vec<Attribute> =
+        vec![syn::parse_quote!(#[column(field = #field, header = #header)])];
+    let column: ColumnAttribute = parse_one(&attrs, "column").unwrap().unwrap();
+    assert_eq!(column.field, field);
+    assert_eq!(column.header.as_deref(), Some(header));
+
+    let (name, function, confirm, enabled_if) = ("Stop", "stop", "Stop {count} VMs?", "is_on");
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[bulk_action(
+        name = #name,
+        fn = #function,
+        confirm = #confirm,
+        enabled_if = #enabled_if
+    )])];
+    let action: ActionAttribute = parse_one(&attrs, "bulk_action").unwrap().unwrap();
+    assert_eq!(action.name, name);
+    assert_eq!(action.function, function);
+    assert_eq!(action.confirm.as_deref(), Some(confirm));
+    assert_eq!(action.enabled_if.as_deref(), Some(enabled_if));
+
+    let refresh = "10s";
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[component(refresh = #refresh)])];
+    let component: ComponentAttribute = parse_one(&attrs, "component").unwrap().unwrap();
+    assert_eq!(component.refresh.unwrap().value(), refresh);
+
+    let text = "Uptime <in days>";
+    let attrs: Vec<Attribute> = vec![syn::parse_quote!(#[label(text = #text)])];
+    let label: LabelAttribute = parse_one(&attrs, "label").unwrap().unwrap();
+    assert_eq!(label.text, text);
+}
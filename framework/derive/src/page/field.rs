@@ -1,133 +1,254 @@
-use proc_macro2::Span;
-use std::fmt::Write;
-use syn::{
-    spanned::Spanned, Attribute, DataStruct, Ident, Lit, Meta, MetaList, MetaNameValue, NestedMeta,
+use super::HtmlBuilder;
+use crate::attributes::{
+    self, ActionAttribute, ColumnAttribute, ComponentAttribute, ExportFormat, LabelAttribute,
+    TableAttribute,
 };
+use proc_macro2::TokenStream;
+use quote::quote;
+use shared::duration::parse_refresh;
+use shared::escape::escape_html;
+use std::fmt::Write;
+use std::time::Duration;
+use syn::{DataStruct, Ident, Type};
 
-pub fn parse(input: DataStruct) -> Result<Vec<Field>, (String, Span)> {
+pub fn parse(input: DataStruct) -> darling::Result<Vec<Field>> {
+    let mut errors = darling::Error::accumulator();
     let mut result = Vec::new();
     for field in input.fields {
         let ident = field.ident.expect("Nameless structs not supported");
-        let attributes = FieldAttributes::parse(&field.attrs)?;
+        let attributes = match errors.handle(FieldAttributes::parse(&ident, &field.attrs)) {
+            Some(attributes) => attributes,
+            None => continue,
+        };
 
-        if attributes.is_table {
+        if let Some(component) = attributes.component {
+            let refresh = match component.refresh {
+                Some(str) => match parse_refresh(&str.value()) {
+                    Ok(dt) => Some(dt),
+                    Err(e) => {
+                        errors.push(darling::Error::custom(e).with_span(&str));
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            result.push(Field::Component(ComponentField {
+                field: ident,
+                ty: field.ty,
+                refresh,
+            }));
+        } else if let Some(table) = attributes.table {
             result.push(Field::Table(TableField {
                 field: ident,
-                actions: attributes.actions,
-                columns: attributes.table_columns,
+                ty: field.ty,
+                selectable: table.selectable,
+                export: table.export.0,
+                actions: attributes.actions.into_iter().map(Into::into).collect(),
+                bulk_actions: attributes
+                    .bulk_actions
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+                columns: attributes.columns.into_iter().map(Into::into).collect(),
             }));
         } else {
             result.push(Field::Label(LabelField {
                 field: ident,
-                label: None,
+                label: attributes.label.map(|label| label.text),
             }));
         }
     }
-    Ok(result)
+    errors.finish_with(result)
 }
 
-#[derive(Default)]
 struct FieldAttributes {
-    is_table: bool,
-    table_columns: Vec<TableColumn>,
-    actions: Vec<Action>,
+    component: Option<ComponentAttribute>,
+    table: Option<TableAttribute>,
+    columns: Vec<ColumnAttribute>,
+    actions: Vec<ActionAttribute>,
+    bulk_actions: Vec<ActionAttribute>,
+    label: Option<LabelAttribute>,
 }
 
 impl FieldAttributes {
-    fn parse(attributes: &[Attribute]) -> Result<Self, (String, Span)> {
-        let mut result = Self::default();
-
-        for attribute in attributes.iter().filter_map(|a| a.parse_meta().ok()) {
-            match attribute {
-                Meta::List(meta) => {
-                    let path = meta.path.get_ident().map(ToString::to_string);
-                    match path.as_deref() {
-                        Some("column") => {
-                            result.parse_column(meta)?;
-                        }
-                        _ => {
-                            continue;
-                        }
-                    }
-                }
-                Meta::Path(path) => {
-                    let path = path.get_ident().map(ToString::to_string);
-                    match path.as_deref() {
-                        Some("table") => {
-                            result.is_table = true;
-                        }
-                        _ => {
-                            return Err(("Unknown path label".to_string(), path.span()));
-                        }
-                    }
-                }
-                Meta::NameValue(path) => unimplemented!("name_value: {:?}", path),
-            };
-        }
+    fn parse(ident: &Ident, attributes: &[syn::Attribute]) -> darling::Result<Self> {
+        let mut errors = darling::Error::accumulator();
+        let result = Self {
+            component: errors
+                .handle(attributes::parse_one(attributes, "component"))
+                .flatten(),
+            table: errors
+                .handle(attributes::parse_one(attributes, "table"))
+                .flatten(),
+            columns: errors
+                .handle(attributes::parse_all(attributes, "column"))
+                .unwrap_or_default(),
+            actions: errors
+                .handle(attributes::parse_all(attributes, "action"))
+                .unwrap_or_default(),
+            bulk_actions: errors
+                .handle(attributes::parse_all(attributes, "bulk_action"))
+                .unwrap_or_default(),
+            label: errors
+                .handle(attributes::parse_one(attributes, "label"))
+                .flatten(),
+        };
+        errors.finish()?;
 
-        Ok(result)
-    }
-
-    fn parse_column(&mut self, meta: MetaList) -> Result<(), (String, Span)> {
-        let mut field = None;
-        let mut header = None;
-        for item in &meta.nested {
-            if let NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) = item {
-                if let Some(ident) = path.get_ident() {
-                    let ident_string = ident.to_string();
-                    match (ident_string.as_str(), lit) {
-                        ("field", Lit::Str(str)) => {
-                            if field.is_some() {
-                                return Err((
-                                    "Duplicate field attribute".to_string(),
-                                    ident.span(),
-                                ));
-                            }
-                            field = Some(str.value());
-                        }
-                        ("header", Lit::Str(str)) => {
-                            if header.is_some() {
-                                return Err((
-                                    "Duplicate header attribute".to_string(),
-                                    ident.span(),
-                                ));
-                            }
-                            header = Some(str.value());
-                        }
-                        _ => {
-                            return Err(("Unknown attribute".to_string(), ident.span()));
-                        }
-                    }
+        for column in &result.columns {
+            if syn::parse_str::<Ident>(&column.field).is_err() {
+                return Err(darling::Error::custom(format!(
+                    "#[column(field = {:?})] is not a valid field name",
+                    column.field
+                ))
+                .with_span(ident));
+            }
+        }
+        for action in result.actions.iter().chain(&result.bulk_actions) {
+            if syn::parse_str::<Ident>(&action.function).is_err() {
+                return Err(darling::Error::custom(format!(
+                    "#[action(fn = {:?})] is not a valid function name",
+                    action.function
+                ))
+                .with_span(ident));
+            }
+            if let Some(enabled_if) = &action.enabled_if {
+                if syn::parse_str::<Ident>(enabled_if).is_err() {
+                    return Err(darling::Error::custom(format!(
+                        "#[action(enabled_if = {:?})] is not a valid method name",
+                        enabled_if
+                    ))
+                    .with_span(ident));
                 }
             }
         }
-        if let Some(field) = field {
-            self.table_columns.push(TableColumn { field, header });
-            Ok(())
-        } else {
-            Err(("Missing 'field = \"...\"'".to_string(), meta.span()))
+        if result
+            .bulk_actions
+            .iter()
+            .any(|action| action.enabled_if.is_some())
+        {
+            return Err(darling::Error::custom(
+                "#[bulk_action] does not support enabled_if, it applies to the selected rows",
+            )
+            .with_span(ident));
+        }
+
+        if result.table.is_none() && !(result.columns.is_empty() && result.actions.is_empty()) {
+            return Err(darling::Error::custom(
+                "#[column] and #[action] can only be used on #[table] fields",
+            )
+            .with_span(ident));
         }
+        let selectable = result.table.as_ref().is_some_and(|table| table.selectable);
+        if !selectable && !result.bulk_actions.is_empty() {
+            return Err(darling::Error::custom(
+                "#[bulk_action] can only be used on #[table(selectable)] fields",
+            )
+            .with_span(ident));
+        }
+        if result.component.is_some() && (result.table.is_some() || result.label.is_some()) {
+            return Err(darling::Error::custom(
+                "#[component] fields can not have a #[table] or #[label]",
+            )
+            .with_span(ident));
+        }
+        if result.table.is_some() && result.label.is_some() {
+            return Err(
+                darling::Error::custom("#[label] can not be used on #[table] fields")
+                    .with_span(ident),
+            );
+        }
+        // The markup and exports look fields up in the model by their name
+        if let Some(rename) = attributes::serde_option(attributes, "rename") {
+            if result.component.is_none() {
+                return Err(darling::Error::custom(
+                    "#[serde(rename)] is not supported on label and #[table] fields",
+                )
+                .with_span(&rename));
+            }
+        }
+        Ok(result)
     }
 }
 
 pub enum Field {
     Label(LabelField),
     Table(TableField),
+    Component(ComponentField),
 }
 
 impl Field {
-    pub fn write_html(&self, out: &mut impl Write) {
+    /// Writes the markup of the field, on the page at `url`.
+    pub fn write_html(&self, url: &str, out: &mut HtmlBuilder) {
         match self {
             Self::Label(inner) => inner.write_html(out),
-            Self::Table(inner) => inner.write_html(out),
+            Self::Table(inner) => inner.write_html(url, out),
+            Self::Component(inner) => inner.write_html(out),
         }
     }
-    pub fn write_javascript(&self, out: &mut impl Write) {
-        match self {
-            Self::Label(inner) => inner.write_javascript(out),
-            Self::Table(inner) => inner.write_javascript(out),
+
+    /// Writes the statements that add to the JSON `model` of the page, on top of what serde
+    /// serializes.
+    pub fn write_model(&self, out: &mut Vec<TokenStream>) {
+        if let Self::Table(inner) = self {
+            inner.write_model(out);
         }
     }
+
+    /// Writes a match arm for every action of this field, dispatching on the action's `fn`.
+    pub fn write_actions(&self, out: &mut Vec<(String, TokenStream)>) {
+        if let Self::Table(inner) = self {
+            inner.write_actions(out);
+        }
+    }
+
+    /// Writes the `framework::export::Table` of a `#[table]` field, for its CSV export.
+    pub fn write_tables(&self, out: &mut Vec<TokenStream>) {
+        if let Self::Table(inner) = self {
+            inner.write_tables(out);
+        }
+    }
+
+    /// Writes a `framework::openapi::Action` for every action of this field.
+    pub fn write_action_list(&self, out: &mut Vec<TokenStream>) {
+        if let Self::Table(inner) = self {
+            inner.write_action_list(out);
+        }
+    }
+
+    /// Writes a method of the `<Page>Client` of `page` for every action of this field.
+    pub fn write_client_methods(&self, page: &Ident, out: &mut Vec<(String, TokenStream)>) {
+        if let Self::Table(inner) = self {
+            inner.write_client_methods(page, out);
+        }
+    }
+}
+
+/// A field of type `framework::Component<P>`, embedding page `P` with its own model, refresh
+/// interval and JSON endpoint.
+pub struct ComponentField {
+    field: Ident,
+    ty: Type,
+    refresh: Option<Duration>,
+}
+
+impl ComponentField {
+    pub fn write_html(&self, out: &mut HtmlBuilder) {
+        let ty = &self.ty;
+        let suffix = format!("_{}", self.field);
+        // Only the default, the overrides of the request apply to components as well
+        let default = match self.refresh {
+            Some(_) => super::refresh_tokens(self.refresh),
+            None => quote! { <#ty as framework::ComponentField>::REFRESH },
+        };
+        out.push_expr(quote! {
+            &<#ty as framework::ComponentField>::render(
+                &format!("{}{}", id, #suffix),
+                #default,
+                context
+            )
+        });
+    }
 }
 
 pub struct LabelField {
@@ -135,61 +256,311 @@ pub struct LabelField {
     field: Ident,
 }
 impl LabelField {
-    pub fn write_html(&self, out: &mut impl Write) {
+    pub fn write_html(&self, out: &mut HtmlBuilder) {
+        let field = self.field.to_string();
         let _ = write!(
             out,
-            "{}: <label data-bind=\"text: {}\"></label>",
-            self.label.clone().unwrap_or_else(|| self.field.to_string()),
-            self.field
+            "{}: <label data-text=\"{}\">",
+            escape_html(self.label.as_ref().unwrap_or(&field)),
+            field
         );
+        // Rendered on the server so the page is readable without javascript
+        out.push_expr(quote! { &framework::ssr::text(&model[#field]) });
+        *out += "</label>";
     }
-    pub fn write_javascript(&self, _out: &mut impl Write) {}
 }
 
 pub struct TableField {
     columns: Vec<TableColumn>,
     actions: Vec<Action>,
+    /// Actions on the rows selected with the checkboxes of a `#[table(selectable)]`
+    bulk_actions: Vec<Action>,
+    selectable: bool,
+    /// The formats of the download links in the table's caption
+    export: Vec<ExportFormat>,
     field: Ident,
+    ty: Type,
 }
 
 impl TableField {
-    pub fn write_html(&self, out: &mut impl Write) {
-        let _ = write!(out, "<table><thead><tr>");
+    pub fn write_html(&self, url: &str, out: &mut HtmlBuilder) {
+        let field = self.field.to_string();
+        if self.selectable {
+            // Groups the bulk actions with the table they act on
+            *out += "<div class=\"framework-table\">";
+            if !self.bulk_actions.is_empty() {
+                *out += "<div class=\"framework-bulk-actions\">";
+                for action in &self.bulk_actions {
+                    let _ = write!(
+                        out,
+                        "<button type=\"button\" data-bulk-action=\"{}\"{} disabled>{}</button>",
+                        action.function,
+                        action.confirm_attribute(),
+                        escape_html(&action.name)
+                    );
+                }
+                *out += "</div>";
+            }
+        }
+        *out += "<table>";
+        if !self.export.is_empty() {
+            *out += "<caption class=\"framework-export\">";
+            for format in &self.export {
+                let extension = format.extension();
+                // The exports are constructed from the same query as the page, so they keep it
+                *out += "<a href=\"";
+                out.push_expr(quote! {
+                    &framework::escape::escape_html(&context.export_href(#url, #extension, #field))
+                });
+                let _ = write!(out, "\" download>{}</a>", format.name());
+            }
+            *out += "</caption>";
+        }
+        let _ = write!(out, "<thead><tr>");
+        if self.selectable {
+            *out +=
+                "<th><input type=\"checkbox\" data-select-all aria-label=\"Select all rows\"></th>";
+        }
         for column in &self.columns {
             let _ = write!(
                 out,
                 "<th>{}</th>",
-                column.header.as_ref().unwrap_or(&column.field)
+                escape_html(column.header.as_ref().unwrap_or(&column.field))
             );
         }
         if !self.actions.is_empty() {
             let _ = write!(out, "<th></th>");
         }
         let _ = write!(out, "</tr></thead>");
-        let _ = write!(out, "<tbody data-bind=\"foreach: {}\">", self.field);
-        let _ = write!(out, "<tr>");
-        for column in &self.columns {
-            let _ = write!(out, "<td data-bind=\"text: {}\"></td>", column.field);
 
-            if !self.actions.is_empty() {
-                let _ = write!(out, "<td>");
-                for action in &self.actions {
-                    let _ = write!(out, "<a href='#'>{}</a>", action.name);
+        // The rows are rendered on the server, new rows are created from the template below
+        let _ = write!(out, "<tbody data-foreach=\"{}\" data-template=\"", field);
+        out.push_expr(quote! { id });
+        let _ = write!(out, "_{}\">", field);
+        let mut row = HtmlBuilder::default();
+        self.write_row(&mut row, true);
+        out.push_for(quote! { row in framework::ssr::rows(&model[#field]) }, row);
+        let _ = write!(out, "</tbody></table><template id=\"");
+        out.push_expr(quote! { id });
+        let _ = write!(out, "_{}\">", field);
+        self.write_row(out, false);
+        let _ = write!(out, "</template>");
+        if self.selectable {
+            *out += "</div>";
+        }
+    }
+
+    pub fn write_model(&self, out: &mut Vec<TokenStream>) {
+        let ident = &self.field;
+        let field = self.field.to_string();
+        // Every row gets its `TableRow::id`, so the client can update rows in place
+        out.push(quote! {
+            framework::ssr::insert_row_ids(&mut model[#field], &self.#ident);
+        });
+        // And whether its actions are enabled, so the client can hide the others
+        for action in &self.actions {
+            if let Some(enabled_if) = &action.enabled_if {
+                let function = &action.function;
+                let enabled_if = Ident::new(enabled_if, ident.span());
+                out.push(quote! {
+                    framework::ssr::insert_row_enabled(
+                        &mut model[#field],
+                        &self.#ident,
+                        #function,
+                        |row| row.#enabled_if(),
+                    );
+                });
+            }
+        }
+    }
+
+    pub fn write_tables(&self, out: &mut Vec<TokenStream>) {
+        let field = self.field.to_string();
+        let columns = self.columns.iter().map(|column| {
+            let field = &column.field;
+            let header = column.header.as_ref().unwrap_or(&column.field);
+            quote! { framework::export::Column { field: #field, header: #header } }
+        });
+        out.push(quote! {
+            framework::export::Table { field: #field, columns: &[#(#columns),*] }
+        });
+    }
+
+    pub fn write_action_list(&self, out: &mut Vec<TokenStream>) {
+        let actions = self.actions.iter().map(|action| (action, false));
+        let bulk_actions = self.bulk_actions.iter().map(|action| (action, true));
+        for (action, bulk) in actions.chain(bulk_actions) {
+            let function = &action.function;
+            let name = &action.name;
+            out.push(quote! {
+                framework::openapi::Action { function: #function, name: #name, bulk: #bulk }
+            });
+        }
+    }
+
+    pub fn write_client_methods(&self, page: &Ident, out: &mut Vec<(String, TokenStream)>) {
+        let ty = &self.ty;
+        let row = quote! { <#ty as framework::client::Rows>::Row };
+        let result = quote! { framework::Result<framework::client::ActionResult<#page>> };
+        for action in &self.actions {
+            let function = Ident::new(&action.function, self.field.span());
+            let name = &action.function;
+            let doc = format!("Invokes `#[action(name = {:?})]` for `row`.", action.name);
+            out.push((
+                action.function.clone(),
+                quote! {
+                    #[doc = #doc]
+                    pub async fn #function(&self, row: &#row) -> #result {
+                        let rows = vec![framework::TableRow::id(row)];
+                        self.client.invoke(<#page as framework::View>::URL, #name, rows).await
+                    }
+                },
+            ));
+        }
+        for action in &self.bulk_actions {
+            let function = Ident::new(&action.function, self.field.span());
+            let name = &action.function;
+            let doc = format!(
+                "Invokes `#[bulk_action(name = {:?})]` for `rows`.",
+                action.name
+            );
+            out.push((
+                action.function.clone(),
+                quote! {
+                    #[doc = #doc]
+                    pub async fn #function(&self, rows: &[#row]) -> #result {
+                        let rows = rows.iter().map(framework::TableRow::id).collect();
+                        self.client.invoke(<#page as framework::View>::URL, #name, rows).await
+                    }
+                },
+            ));
+        }
+    }
+
+    pub fn write_actions(&self, out: &mut Vec<(String, TokenStream)>) {
+        let ident = &self.field;
+        for action in &self.actions {
+            let function = Ident::new(&action.function, ident.span());
+            let name = &action.function;
+            // The link was rendered for an older model, so check `enabled_if` again
+            let ensure_enabled = action.enabled_if.as_ref().map(|enabled_if| {
+                let enabled_if = Ident::new(enabled_if, ident.span());
+                quote! {
+                    framework::ssr::ensure_enabled(row.#enabled_if(), #name, &row)?;
                 }
-                let _ = write!(out, "</td>");
+            });
+            out.push((
+                action.function.clone(),
+                quote! {
+                    let row = framework::ssr::select_row(&self.#ident, &rows)?;
+                    #ensure_enabled
+                    framework::IntoActionResult::into_action_result(
+                        self.#function(request, row).await,
+                    )
+                },
+            ));
+        }
+        for action in &self.bulk_actions {
+            let function = Ident::new(&action.function, ident.span());
+            // Selections are sent as `TableRow::id`s, rows that disappeared in the meantime
+            // are skipped
+            out.push((
+                action.function.clone(),
+                quote! {
+                    let rows = framework::ssr::select_rows(&self.#ident, &rows);
+                    framework::IntoActionResult::into_action_result(
+                        self.#function(request, rows).await,
+                    )
+                },
+            ));
+        }
+    }
+
+    /// Writes a single row, either as template or filled in with the values of `row`.
+    fn write_row(&self, out: &mut HtmlBuilder, server_side: bool) {
+        if server_side {
+            *out += "<tr data-key=\"";
+            out.push_expr(quote! { &framework::ssr::text(&row["_id"]) });
+            *out += "\">";
+        } else {
+            *out += "<tr>";
+        }
+        if self.selectable {
+            *out += "<td><input type=\"checkbox\" data-select aria-label=\"Select row\"></td>";
+        }
+        for column in &self.columns {
+            let _ = write!(out, "<td data-text=\"{}\">", column.field);
+            if server_side {
+                let column = &column.field;
+                out.push_expr(quote! { &framework::ssr::text(&row[#column]) });
+            }
+            let _ = write!(out, "</td>");
+        }
+        // A single cell with all actions, below the empty `<th>` of the header
+        if !self.actions.is_empty() {
+            let _ = write!(out, "<td>");
+            for action in &self.actions {
+                let _ = write!(
+                    out,
+                    "<button type=\"button\" data-action=\"{}\"{}",
+                    action.function,
+                    action.confirm_attribute()
+                );
+                if action.enabled_if.is_some() {
+                    let _ = write!(out, " data-enabled=\"{}\"", action.function);
+                    if server_side {
+                        let function = &action.function;
+                        out.push_expr(quote! {
+                            framework::ssr::hidden_unless_enabled(&row, #function)
+                        });
+                    }
+                }
+                let _ = write!(out, ">{}</button>", escape_html(&action.name));
             }
+            let _ = write!(out, "</td>");
         }
-        let _ = write!(out, "</tr></tbody></table>");
+        let _ = write!(out, "</tr>");
     }
-    pub fn write_javascript(&self, _out: &mut impl Write) {}
 }
 
 pub struct Action {
     pub name: String,
-    pub action: String,
+    pub function: String,
+    pub confirm: Option<String>,
+    pub enabled_if: Option<String>,
+}
+
+impl Action {
+    /// The `data-confirm` attribute, script.js fills in the `{field}` placeholders.
+    fn confirm_attribute(&self) -> String {
+        match &self.confirm {
+            Some(confirm) => format!(" data-confirm=\"{}\"", escape_html(confirm)),
+            None => String::new(),
+        }
+    }
+}
+
+impl From<ActionAttribute> for Action {
+    fn from(attribute: ActionAttribute) -> Self {
+        Self {
+            name: attribute.name,
+            function: attribute.function,
+            confirm: attribute.confirm,
+            enabled_if: attribute.enabled_if,
+        }
+    }
 }
 
 pub struct TableColumn {
     pub field: String,
     pub header: Option<String>,
 }
+
+impl From<ColumnAttribute> for TableColumn {
+    fn from(attribute: ColumnAttribute) -> Self {
+        Self {
+            field: attribute.field,
+            header: attribute.header,
+        }
+    }
+}
@@ -1,102 +1,911 @@
 mod field;
 
-use proc_macro2::{Span, TokenStream};
-use quote::quote;
+use crate::attributes::{self, PageAttribute};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use shared::duration::parse_refresh;
 use std::fmt::Write;
 use std::time::Duration;
-use syn::{Attribute, Data, DeriveInput, Ident, Lit, Meta, MetaNameValue, NestedMeta};
+use syn::{Attribute, Data, DeriveInput, Ident, LitStr};
 
-pub fn generate(input: DeriveInput) -> Result<TokenStream, (String, Span)> {
+pub fn generate(input: DeriveInput) -> darling::Result<TokenStream> {
     let ident = input.ident;
     let attributes = StructAttributes::parse(&ident, &input.attrs)?;
     let fields = if let Data::Struct(input) = input.data {
         field::parse(input)?
     } else {
-        return Err(("Only structs are supported".to_string(), ident.span()));
+        return Err(darling::Error::custom("Only structs are supported").with_span(&ident));
     };
 
-    let url = attributes.url;
-    let mut html = format!("<div id=\"page_{}\" data-bind=\"with: model\">", ident);
-    let mut javascript = String::new();
-    for field in fields {
-        field.write_html(&mut html);
-        field.write_javascript(&mut javascript);
+    let url = &attributes.url;
+    let id = format!("page_{}", ident);
+    let html = render_html(url, &fields).finish();
+    let mut model = Vec::new();
+    let mut actions = Vec::new();
+    let mut action_list = Vec::new();
+    let mut tables = Vec::new();
+    for field in &fields {
+        field.write_model(&mut model);
+        field.write_actions(&mut actions);
+        field.write_action_list(&mut action_list);
+        field.write_tables(&mut tables);
     }
-    html += "</div><script type='text/javascript'>";
-    html += &javascript;
-    let _ = write!(&mut html, "load('page_{}', {{}});", ident);
-    if let Some(refresh) = attributes.refresh {
-        let _ = write!(&mut html, "enable_auto_reload({});", refresh.as_millis());
+
+    let refresh = refresh_tokens(attributes.refresh);
+    let cache = refresh_tokens(attributes.cache);
+    let cache_per_user = attributes.cache_per_user;
+    let actions = actions_impl(&ident, attributes.state, actions, action_list)?;
+    let client = if cfg!(feature = "client") {
+        client_impl(&input.vis, &ident, &fields)?
+    } else {
+        TokenStream::new()
+    };
+
+    Ok(quote! {
+        impl framework::View for #ident {
+            const URL: &'static str = #url;
+            const REFRESH: Option<std::time::Duration> = #refresh;
+            const CACHE: Option<std::time::Duration> = #cache;
+            const CACHE_PER_USER: bool = #cache_per_user;
+            const TABLES: &'static [framework::export::Table] = &[#(#tables),*];
+
+            fn render(
+                id: &str,
+                model: &framework::serde_json::Value,
+                interval: Option<std::time::Duration>,
+                context: &framework::RenderContext,
+            ) -> String {
+                let interval = match interval {
+                    Some(interval) => interval.as_millis().to_string(),
+                    None => String::new(),
+                };
+                let mut html = String::new();
+                #(#html)*
+                html
+            }
+
+            fn model(&self) -> framework::serde_json::Value {
+                #[allow(unused_mut)]
+                let mut model = framework::serde_json::to_value(self).unwrap();
+                #(#model)*
+                model
+            }
+
+            fn html(&self, context: &framework::RenderContext) -> String {
+                let interval = context.interval(Self::URL, Self::REFRESH);
+                Self::render(#id, &self.model(), interval, context)
+            }
+        }
+
+        #actions
+
+        #client
+    })
+}
+
+/// Builds the statements of `View::render`, which writes the page at `url` with its `fields`.
+fn render_html(url: &str, fields: &[field::Field]) -> HtmlBuilder {
+    // Pages are bootstrapped by script.js from these data attributes, so no inline script is
+    // needed and a strict Content-Security-Policy can be used
+    let mut html = HtmlBuilder::default();
+    html += "<div id=\"";
+    html.push_expr(quote! { id });
+    html += "\" data-page data-url=\"";
+    html.push_expr(quote! { &framework::escape::escape_html(&context.json_url(#url)) });
+    html += "\" data-refresh=\"";
+    html.push_expr(quote! { &interval });
+    html += "\">";
+    for field in fields {
+        field.write_html(url, &mut html);
     }
+    // The model is embedded as a data island rather than as a javascript literal, so a value
+    // containing `</script>` can never break out of it
+    html += "</div><script type=\"application/json\" id=\"";
+    html.push_expr(quote! { id });
+    html += "_model\">";
+    html.push_expr(quote! { &framework::escape::escape_json(&model.to_string()) });
     html += "</script>";
+    html
+}
 
+/// Generates `<ident>Client`, a typed client for the page with a method for every action.
+fn client_impl(
+    vis: &syn::Visibility,
+    ident: &Ident,
+    fields: &[field::Field],
+) -> darling::Result<TokenStream> {
+    let client = format_ident!("{}Client", ident);
+    let mut methods = Vec::new();
+    for field in fields {
+        field.write_client_methods(ident, &mut methods);
+    }
+    if let Some((function, _)) = methods
+        .iter()
+        .find(|(function, _)| ["new", "basic_auth", "fetch"].contains(&function.as_str()))
+    {
+        return Err(darling::Error::custom(format!(
+            "Action fn = {:?} clashes with a method of the generated {}",
+            function, client
+        ))
+        .with_span(ident));
+    }
+    let methods = methods.into_iter().map(|(_, method)| method);
+    let doc = format!("A typed client for [`{}`], see `framework::client`.", ident);
     Ok(quote! {
-        impl framework::Page for #ident {
-            const URL: &'static str = #url;
+        #[doc = #doc]
+        #[derive(Clone, Debug)]
+        #vis struct #client {
+            client: framework::client::Client,
+        }
+
+        impl #client {
+            /// A client for the service at `base_url`, e.g. `http://localhost:8080`.
+            pub fn new(base_url: &str) -> framework::Result<Self> {
+                Ok(Self {
+                    client: framework::client::Client::new(base_url)?,
+                })
+            }
+
+            /// Logs in with every request.
+            pub fn basic_auth(self, user: &str, password: &str) -> Self {
+                Self {
+                    client: self.client.basic_auth(user, password),
+                }
+            }
+
+            /// The model of the page.
+            pub async fn fetch(&self) -> framework::Result<#ident> {
+                self.client.fetch(<#ident as framework::View>::URL).await
+            }
+
+            #(#methods)*
+        }
+    })
+}
+
+/// Generates the `framework::Actions` implementation, dispatching an action to the `fn` of the
+/// `(fn, body)` pair with the same name.
+///
+/// Actions receive a `Request<state>`, so a page with actions only implements `Actions` for the
+/// `#[page(state = "...")]` type, which defaults to `()`.
+fn actions_impl(
+    ident: &Ident,
+    state: Option<syn::Type>,
+    actions: Vec<(String, TokenStream)>,
+    action_list: Vec<TokenStream>,
+) -> darling::Result<TokenStream> {
+    let mut seen = std::collections::HashSet::new();
+    for (function, _) in &actions {
+        if !seen.insert(function) {
+            return Err(
+                darling::Error::custom(format!("Duplicate action fn = {:?}", function))
+                    .with_span(ident),
+            );
+        }
+    }
 
-            fn html(self) -> String {
-                format!(#html, serde_json::to_string(&self).unwrap())
+    let (generics, state) = match (state, actions.is_empty()) {
+        (Some(state), _) => (quote! {}, quote! { #state }),
+        (None, false) => (quote! {}, quote! { () }),
+        (None, true) => (
+            quote! { <__State: Send + Sync + 'static> },
+            quote! { __State },
+        ),
+    };
+    let (functions, bodies): (Vec<_>, Vec<_>) = actions.into_iter().unzip();
+    Ok(quote! {
+        #[framework::prelude::async_trait]
+        impl #generics framework::Actions<#state> for #ident {
+            const ACTIONS: &'static [framework::openapi::Action] = &[#(#action_list),*];
+
+            #[allow(unused_variables)]
+            async fn invoke(
+                &mut self,
+                function: &str,
+                request: framework::Request<#state>,
+                rows: Vec<String>,
+            ) -> framework::Result<Option<framework::Job>> {
+                match function {
+                    #(#functions => { #bodies })*
+                    _ => Err(framework::Error::NotFound(format!("Unknown action {:?}", function))),
+                }
             }
         }
     })
 }
 
+/// Generates `Option<Duration>` tokens for a refresh interval parsed at compile time.
+fn refresh_tokens(refresh: Option<Duration>) -> TokenStream {
+    match refresh {
+        Some(refresh) => {
+            let millis = refresh.as_millis() as u64;
+            quote! { Some(std::time::Duration::from_millis(#millis)) }
+        }
+        None => quote! { None },
+    }
+}
+
+/// Collects the statements that build a page's HTML at runtime.
+///
+/// Static markup is written through [`Write`] and merged into a single `push_str`, while
+/// expressions that are only known at runtime (like the page id or embedded components) are
+/// appended with [`HtmlBuilder::push_expr`].
 #[derive(Default)]
-struct StructAttributes {
-    url: String,
-    refresh: Option<Duration>,
+struct HtmlBuilder {
+    parts: Vec<HtmlPart>,
 }
 
-impl StructAttributes {
-    fn parse(ident: &Ident, attributes: &[Attribute]) -> Result<Self, (String, Span)> {
-        let mut result = Self::default();
-
-        for attribute in attributes.iter().filter_map(|a| a.parse_meta().ok()) {
-            let meta = match attribute {
-                Meta::List(list) => list,
-                _ => continue,
-            };
-            if meta.path.get_ident().map(ToString::to_string).as_deref() != Some("page") {
-                continue;
-            }
+enum HtmlPart {
+    Text(String),
+    Expr(TokenStream),
+    For(TokenStream, Vec<HtmlPart>),
+}
 
-            for item in meta.nested {
-                if let NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) = item {
-                    if let Some(ident) = path.get_ident() {
-                        let ident_string = ident.to_string();
-                        match (ident_string.as_str(), lit) {
-                            ("path", Lit::Str(str)) => result.url = str.value(),
-                            ("refresh", Lit::Str(str)) => {
-                                if let Some(dt) = parse_datetime(str.value()) {
-                                    result.refresh = Some(dt);
-                                } else {
-                                    return Err((String::from("Invalid duration"), str.span()));
-                                }
+impl HtmlBuilder {
+    fn push_expr(&mut self, expr: TokenStream) {
+        self.parts.push(HtmlPart::Expr(expr));
+    }
+
+    /// Repeats `body` for every iteration of `for #iter { ... }`.
+    fn push_for(&mut self, iter: TokenStream, body: HtmlBuilder) {
+        self.parts.push(HtmlPart::For(iter, body.parts));
+    }
+
+    fn finish(self) -> Vec<TokenStream> {
+        fn statements(parts: Vec<HtmlPart>) -> Vec<TokenStream> {
+            parts
+                .into_iter()
+                .map(|part| match part {
+                    HtmlPart::Text(text) => quote! { html.push_str(#text); },
+                    HtmlPart::Expr(expr) => quote! { html.push_str(#expr); },
+                    HtmlPart::For(iter, body) => {
+                        let body = statements(body);
+                        quote! {
+                            for #iter {
+                                #(#body)*
                             }
-                            (_, _) => return Err(("Unknown attribute".to_string(), ident.span())),
                         }
                     }
+                })
+                .collect()
+        }
+        statements(self.parts)
+    }
+
+    /// The markup as a template for snapshot tests, with `{{ expr }}` for runtime expressions
+    /// and `{{ for iter }}...{{ end }}` for loops.
+    #[cfg(test)]
+    fn template(&self) -> String {
+        fn write(out: &mut String, parts: &[HtmlPart]) {
+            for part in parts {
+                match part {
+                    HtmlPart::Text(text) => out.push_str(text),
+                    HtmlPart::Expr(expr) => *out += &format!("{{{{ {} }}}}", expr),
+                    HtmlPart::For(iter, body) => {
+                        *out += &format!("\n{{{{ for {} }}}}\n", iter);
+                        write(out, body);
+                        *out += "\n{{ end }}\n";
+                    }
                 }
             }
         }
+        let mut out = String::new();
+        write(&mut out, &self.parts);
+        out
+    }
+}
 
-        if result.url.is_empty() {
-            Err((
-                "Missing attribute #[page(path = \"...\")]".to_string(),
-                ident.span(),
-            ))
-        } else {
-            Ok(result)
+impl Write for HtmlBuilder {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        match self.parts.last_mut() {
+            Some(HtmlPart::Text(text)) => text.push_str(s),
+            _ => self.parts.push(HtmlPart::Text(s.to_string())),
         }
+        Ok(())
     }
 }
 
-fn parse_datetime(str: String) -> Option<Duration> {
-    if let Some(ms) = str.strip_suffix("ms") {
-        ms.parse().map(Duration::from_millis).ok()
-    } else if let Some(sec) = str.strip_suffix('s') {
-        sec.parse().map(Duration::from_secs).ok()
-    } else {
-        None
+impl std::ops::AddAssign<&str> for HtmlBuilder {
+    fn add_assign(&mut self, s: &str) {
+        let _ = self.write_str(s);
+    }
+}
+
+struct StructAttributes {
+    url: String,
+    refresh: Option<Duration>,
+    cache: Option<Duration>,
+    cache_per_user: bool,
+    state: Option<syn::Type>,
+}
+
+impl StructAttributes {
+    fn parse(ident: &Ident, attributes: &[Attribute]) -> darling::Result<Self> {
+        let page: PageAttribute = attributes::parse_one(attributes, "page")?.ok_or_else(|| {
+            darling::Error::custom("Missing attribute #[page(path = \"...\")]").with_span(ident)
+        })?;
+        let valid_path = page.path.starts_with('/')
+            && page
+                .path
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "/-_.~".contains(c));
+        if !valid_path {
+            return Err(darling::Error::custom(
+                "#[page(path)] must start with a '/' and only contain letters, digits and '/-_.~'",
+            )
+            .with_span(ident));
+        }
+
+        // Renamed fields would not be found in the model by their name, see `field::parse`
+        if let Some(rename_all) = attributes::serde_option(attributes, "rename_all") {
+            return Err(
+                darling::Error::custom("#[serde(rename_all)] is not supported on pages")
+                    .with_span(&rename_all),
+            );
+        }
+
+        let parse = |str: Option<LitStr>| match str {
+            Some(str) => match parse_refresh(&str.value()) {
+                Ok(dt) => Ok(Some(dt)),
+                Err(e) => Err(darling::Error::custom(e).with_span(&str)),
+            },
+            None => Ok(None),
+        };
+        let refresh = parse(page.refresh)?;
+        let cache = parse(page.cache)?;
+        if page.cache_per_user && cache.is_none() {
+            return Err(
+                darling::Error::custom("#[page(cache_per_user)] requires cache = \"...\"")
+                    .with_span(ident),
+            );
+        }
+
+        Ok(Self {
+            url: page.path,
+            refresh,
+            cache,
+            cache_per_user: page.cache_per_user,
+            state: page.state,
+        })
+    }
+}
+
+#[test]
+fn test_generate_parses_all_attributes() {
+    let input: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms", refresh = "5s")]
+        struct Vms {
+            #[label(text = "Host")]
+            host: String,
+            #[table]
+            #[column(field = "name", header = "Name")]
+            #[column(field = "state")]
+            #[action(name = "on", fn = "turn_on")]
+            vms: Vec<Vm>,
+        }
+    };
+    let output = generate(input).unwrap().to_string();
+    assert!(output.contains("\"/vms\""), "{}", output);
+    assert!(output.contains("Host: <label"), "{}", output);
+    assert!(output.contains("<th>Name</th><th>state</th>"), "{}", output);
+    assert!(
+        output.contains("context . json_url (\"/vms\")"),
+        "{}",
+        output
+    );
+    assert!(!output.contains("text/javascript"), "{}", output);
+    assert!(output.contains("from_millis (5000u64)"), "{}", output);
+    assert!(
+        output.contains("const CACHE : Option < std :: time :: Duration > = None"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("insert_row_ids (& mut model [\"vms\"] , & self . vms)"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn test_generate_escapes_hostile_attributes() {
+    let input: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[label(text = "<script>alert('label')</script>")]
+            host: String,
+            #[table]
+            #[column(field = "name", header = "</th><script>alert(\"header\")</script>")]
+            #[action(name = "<img src=x onerror=alert(1)>", fn = "turn_on")]
+            vms: Vec<Vm>,
+        }
+    };
+    let output = generate(input).unwrap().to_string();
+    // The raw header is only kept for the CSV export, outside of the rendered HTML
+    let render = &output[output.find("fn render").unwrap()..output.find("fn model").unwrap()];
+    assert!(!render.contains("<script>alert"), "{}", output);
+    assert!(!render.contains("<img"), "{}", output);
+    assert!(
+        output.contains("&lt;script&gt;alert(&#39;label&#39;)&lt;/script&gt;"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("&lt;/th&gt;&lt;script&gt;alert(&quot;header&quot;)"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("escape_json (& model . to_string ())"),
+        "{}",
+        output
+    );
+
+    let hostile_field: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table]
+            #[column(field = "name\"><script>alert(1)</script>")]
+            vms: Vec<Vm>,
+        }
+    };
+    assert!(generate(hostile_field).is_err());
+
+    let hostile_path: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms');alert(1);//")]
+        struct Vms { host: String }
+    };
+    assert!(generate(hostile_path).is_err());
+}
+
+#[test]
+fn test_generate_components() {
+    let input: DeriveInput = syn::parse_quote! {
+        #[page(path = "/dashboard")]
+        struct Dashboard {
+            #[component]
+            uptime: Component<Uptime>,
+            #[component(refresh = "1m")]
+            vms: Component<Vms>,
+        }
+    };
+    let output = generate(input).unwrap().to_string();
+    assert!(output.contains("\"_uptime\""), "{}", output);
+    assert!(output.contains("ComponentField > :: REFRESH"), "{}", output);
+    assert!(output.contains("from_millis (60000u64)"), "{}", output);
+    assert!(!output.contains("data-bind"), "{}", output);
+
+    let mixed: DeriveInput = syn::parse_quote! {
+        #[page(path = "/dashboard")]
+        struct Dashboard {
+            #[component]
+            #[table]
+            vms: Component<Vms>,
+        }
+    };
+    assert!(generate(mixed).is_err());
+}
+
+#[test]
+fn test_generate_bulk_actions() {
+    let input: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table(selectable)]
+            #[column(field = "name")]
+            #[bulk_action(name = "Stop", fn = "stop")]
+            vms: Vec<Vm>,
+        }
+    };
+    let output = generate(input).unwrap().to_string();
+    assert!(output.contains("data-select-all"), "{}", output);
+    assert!(
+        output.contains("data-bulk-action=\\\"stop\\\""),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("framework :: Actions < () > for Vms"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("\"stop\" => { let rows = framework :: ssr :: select_rows (& self . vms , & rows) ; framework :: IntoActionResult :: into_action_result (self . stop (request , rows) . await ,) }"),
+        "{}",
+        output
+    );
+
+    let with_state: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms", state = "AppState")]
+        struct Vms {
+            #[table(selectable)]
+            #[bulk_action(name = "Stop", fn = "stop")]
+            vms: Vec<Vm>,
+        }
+    };
+    let output = generate(with_state).unwrap().to_string();
+    assert!(
+        output.contains("Actions < AppState > for Vms"),
+        "{}",
+        output
+    );
+
+    // Pages without actions work with any state
+    let without_actions: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table]
+            vms: Vec<Vm>,
+        }
+    };
+    let output = generate(without_actions).unwrap().to_string();
+    assert!(output.contains("Actions < __State > for Vms"), "{}", output);
+    assert!(!output.contains("data-select"), "{}", output);
+
+    let not_selectable: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table]
+            #[bulk_action(name = "Stop", fn = "stop")]
+            vms: Vec<Vm>,
+        }
+    };
+    let err = generate(not_selectable).unwrap_err();
+    assert!(err.to_string().contains("#[table(selectable)]"), "{}", err);
+
+    let duplicate: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table(selectable)]
+            #[bulk_action(name = "Stop", fn = "stop")]
+            a: Vec<Vm>,
+            #[table(selectable)]
+            #[bulk_action(name = "Stop", fn = "stop")]
+            b: Vec<Vm>,
+        }
+    };
+    let err = generate(duplicate).unwrap_err();
+    assert!(err.to_string().contains("Duplicate action"), "{}", err);
+}
+
+#[test]
+fn test_generate_confirm_and_enabled_if() {
+    let input: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table(selectable)]
+            #[column(field = "name")]
+            #[action(name = "off", fn = "turn_off", enabled_if = "is_running", confirm = "Shut down <{name}>?")]
+            #[bulk_action(name = "Stop", fn = "stop", confirm = "Stop {count} VMs?")]
+            vms: Vec<Vm>,
+        }
+    };
+    let output = generate(input).unwrap().to_string();
+    assert!(
+        output.contains("data-confirm=\\\"Shut down &lt;{name}&gt;?\\\""),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("data-confirm=\\\"Stop {count} VMs?\\\""),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("data-enabled=\\\"turn_off\\\""),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("insert_row_enabled (& mut model [\"vms\"] , & self . vms , \"turn_off\" , | row | row . is_running () ,)"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("hidden_unless_enabled (& row , \"turn_off\")"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("\"turn_off\" => { let row = framework :: ssr :: select_row (& self . vms , & rows) ? ; framework :: ssr :: ensure_enabled (row . is_running () , \"turn_off\" , & row) ? ; framework :: IntoActionResult :: into_action_result (self . turn_off (request , row) . await ,) }"),
+        "{}",
+        output
+    );
+
+    let invalid_method: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table]
+            #[action(name = "off", fn = "turn_off", enabled_if = "state == 'running'")]
+            vms: Vec<Vm>,
+        }
+    };
+    let err = generate(invalid_method).unwrap_err();
+    assert!(err.to_string().contains("enabled_if"), "{}", err);
+
+    let bulk_enabled_if: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table(selectable)]
+            #[bulk_action(name = "Stop", fn = "stop", enabled_if = "is_running")]
+            vms: Vec<Vm>,
+        }
+    };
+    assert!(generate(bulk_enabled_if).is_err());
+}
+
+#[test]
+fn test_client_impl() {
+    let fields = |input: DeriveInput| match input.data {
+        Data::Struct(data) => field::parse(data).unwrap(),
+        _ => unreachable!(),
+    };
+    let ident: Ident = syn::parse_quote!(Vms);
+    let vis: syn::Visibility = syn::parse_quote!(pub);
+    let input: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table(selectable)]
+            #[action(name = "On", fn = "turn_on")]
+            #[bulk_action(name = "Stop", fn = "stop")]
+            vms: Vec<Vm>,
+        }
+    };
+    let output = client_impl(&vis, &ident, &fields(input))
+        .unwrap()
+        .to_string();
+    assert!(output.contains("pub struct VmsClient"), "{}", output);
+    assert!(
+        output.contains("pub async fn fetch (& self) -> framework :: Result < Vms >"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("pub async fn turn_on (& self , row : & < Vec < Vm > as framework :: client :: Rows > :: Row)"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("pub async fn stop (& self , rows : & [< Vec < Vm > as framework :: client :: Rows > :: Row])"),
+        "{}",
+        output
+    );
+
+    let clash: DeriveInput = syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table]
+            #[action(name = "Fetch", fn = "fetch")]
+            vms: Vec<Vm>,
+        }
+    };
+    let err = client_impl(&vis, &ident, &fields(clash)).unwrap_err();
+    assert!(err.to_string().contains("clashes"), "{}", err);
+}
+
+#[test]
+fn test_generate_reports_invalid_attributes() {
+    let missing_page: DeriveInput = syn::parse_quote! {
+        struct Foo { bar: String }
+    };
+    let err = generate(missing_page).unwrap_err();
+    assert!(err.to_string().contains("#[page"), "{}", err);
+
+    let cache_per_user_without_cache: DeriveInput = syn::parse_quote! {
+        #[page(path = "/foo", cache_per_user)]
+        struct Foo { bar: String }
+    };
+    let err = generate(cache_per_user_without_cache).unwrap_err();
+    assert!(err.to_string().contains("cache_per_user"), "{}", err);
+
+    let invalid_cache: DeriveInput = syn::parse_quote! {
+        #[page(path = "/foo", cache = "soon")]
+        struct Foo { bar: String }
+    };
+    assert!(generate(invalid_cache).is_err());
+
+    let column_without_table: DeriveInput = syn::parse_quote! {
+        #[page(path = "/foo")]
+        struct Foo {
+            #[column(field = "a")]
+            bar: Vec<Bar>,
+        }
+    };
+    let err = generate(column_without_table).unwrap_err();
+    assert!(err.to_string().contains("#[table]"), "{}", err);
+
+    let multiple_errors: DeriveInput = syn::parse_quote! {
+        #[page(path = "/foo")]
+        struct Foo {
+            #[table]
+            #[column(header = "A")]
+            a: Vec<Bar>,
+            #[label(txt = "B")]
+            b: String,
+        }
+    };
+    let err = generate(multiple_errors).unwrap_err();
+    assert_eq!(err.len(), 3, "{}", err);
+
+    let renamed: DeriveInput = syn::parse_quote! {
+        #[page(path = "/foo")]
+        struct Foo {
+            #[table]
+            #[serde(rename = "machines")]
+            vms: Vec<Vm>,
+            #[serde(default, rename(serialize = "Bar"))]
+            bar: String,
+            #[component]
+            #[serde(rename = "widget")]
+            uptime: Component<Uptime>,
+        }
+    };
+    let err = generate(renamed).unwrap_err();
+    assert_eq!(err.len(), 2, "{}", err);
+    assert!(err.to_string().contains("#[serde(rename)]"), "{}", err);
+
+    let renamed_all: DeriveInput = syn::parse_quote! {
+        #[page(path = "/foo")]
+        #[serde(rename_all = "camelCase")]
+        struct Foo { bar_baz: String }
+    };
+    let err = generate(renamed_all).unwrap_err();
+    assert!(err.to_string().contains("rename_all"), "{}", err);
+
+    let too_fast: DeriveInput = syn::parse_quote! {
+        #[page(path = "/foo", refresh = "10ms")]
+        struct Foo { bar: String }
+    };
+    let err = generate(too_fast).unwrap_err();
+    assert!(err.to_string().contains("too small"), "{}", err);
+}
+
+/// The URL, refresh interval, HTML template and model statements of the page `input`, for
+/// snapshot tests.
+///
+/// The generated markup has no line breaks, so one is added before every opening tag to keep
+/// the snapshots readable. Review changed snapshots with `cargo insta review`.
+#[cfg(test)]
+fn snapshot(input: DeriveInput) -> String {
+    let attributes = StructAttributes::parse(&input.ident, &input.attrs).unwrap();
+    let Data::Struct(data) = input.data else {
+        panic!("Only structs are supported");
+    };
+    let fields = field::parse(data).unwrap();
+    let html = render_html(&attributes.url, &fields)
+        .template()
+        .replace("><", ">\n<")
+        .replace(">\n</", "></");
+    let mut model = Vec::new();
+    let mut tables = Vec::new();
+    for field in &fields {
+        field.write_model(&mut model);
+        field.write_tables(&mut tables);
     }
+    let model: Vec<_> = model.iter().map(ToString::to_string).collect();
+    let tables: Vec<_> = tables.iter().map(ToString::to_string).collect();
+    format!(
+        "URL = {}\nREFRESH = {}\n\n{}\n\nMODEL:\n{}\n\nTABLES:\n{}\n",
+        attributes.url,
+        refresh_tokens(attributes.refresh),
+        html,
+        model.join("\n"),
+        tables.join("\n")
+    )
+}
+
+#[test]
+fn test_snapshot_labels() {
+    insta::assert_snapshot!(snapshot(syn::parse_quote! {
+        #[page(path = "/uptime", refresh = "1.5s")]
+        struct Uptime {
+            uptime: String,
+            #[label(text = "Host name")]
+            host: String,
+        }
+    }));
+}
+
+#[test]
+fn test_snapshot_table() {
+    insta::assert_snapshot!(snapshot(syn::parse_quote! {
+        #[page(path = "/vms", refresh = "1m30s")]
+        struct Vms {
+            #[table]
+            #[column(field = "name", header = "Name")]
+            #[column(field = "state")]
+            vms: Vec<Vm>,
+        }
+    }));
+}
+
+#[test]
+fn test_snapshot_table_with_actions() {
+    insta::assert_snapshot!(snapshot(syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table]
+            #[column(field = "name", header = "Name")]
+            #[column(field = "state", header = "State")]
+            #[action(name = "Start", fn = "turn_on", enabled_if = "is_shut_off")]
+            #[action(name = "Stop", fn = "turn_off", confirm = "Really stop {name}?")]
+            vms: Vec<Vm>,
+        }
+    }));
+}
+
+#[test]
+fn test_snapshot_selectable_table() {
+    insta::assert_snapshot!(snapshot(syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table(selectable)]
+            #[column(field = "name", header = "Name")]
+            #[bulk_action(name = "Start", fn = "start")]
+            #[bulk_action(name = "Stop", fn = "stop", confirm = "Stop {count} VMs?")]
+            vms: Vec<Vm>,
+        }
+    }));
+}
+
+#[test]
+fn test_snapshot_exported_table() {
+    insta::assert_snapshot!(snapshot(syn::parse_quote! {
+        #[page(path = "/vms")]
+        struct Vms {
+            #[table(export = ["csv", "xlsx"])]
+            #[column(field = "name", header = "Name")]
+            #[column(field = "state")]
+            vms: Vec<Vm>,
+        }
+    }));
+}
+
+#[test]
+fn test_snapshot_components() {
+    insta::assert_snapshot!(snapshot(syn::parse_quote! {
+        #[page(path = "/dashboard")]
+        struct Dashboard {
+            #[component]
+            uptime: Component<Uptime>,
+            #[component(refresh = "10s")]
+            vms: Component<Vms>,
+        }
+    }));
+}
+
+#[test]
+fn test_snapshot_edge_cases() {
+    // No fields at all, and a table without columns
+    insta::assert_snapshot!(
+        "empty_page",
+        snapshot(syn::parse_quote! {
+            #[page(path = "/empty")]
+            struct Empty {}
+        })
+    );
+    insta::assert_snapshot!(
+        "table_without_columns",
+        snapshot(syn::parse_quote! {
+            #[page(path = "/vms")]
+            struct Vms {
+                #[table]
+                vms: Vec<Vm>,
+            }
+        })
+    );
+    insta::assert_snapshot!(
+        "hostile_attributes",
+        snapshot(syn::parse_quote! {
+            #[page(path = "/a-b_c.d~e/f")]
+            struct Hostile {
+                #[label(text = "<script>alert('label')</script>")]
+                host: String,
+                #[table]
+                #[column(field = "name", header = "</th><script>alert(\"header\")</script>")]
+                #[action(name = "<img src=x onerror=alert(1)>", fn = "turn_on", confirm = "\"&'<>")]
+                vms: Vec<Vm>,
+            }
+        })
+    );
 }
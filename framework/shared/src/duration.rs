@@ -0,0 +1,121 @@
+//! Parsing of human readable durations like `"500ms"`, `"1.5s"` or `"1m30s"`.
+//!
+//! [`parse_duration`] accepts any sum of parts in `ms`, `s`, `m` and `h`, and [`parse_refresh`]
+//! also rejects refresh intervals below [`MIN_REFRESH`].
+
+use std::time::Duration;
+
+/// The smallest refresh interval that is accepted, anything faster would just hammer the server.
+pub const MIN_REFRESH: Duration = Duration::from_millis(100);
+
+/// Parses a duration made of one or more `<number><unit>` parts, e.g. `"1h"`, `"1.5s"` or
+/// `"1m 30s"`. Supported units are `ms`, `s`, `m` and `h`.
+pub fn parse_duration(str: &str) -> Result<Duration, String> {
+    let mut rest = str.trim();
+    if rest.is_empty() {
+        return Err("Duration is empty, expected something like \"5s\"".to_string());
+    }
+
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_len);
+        let unit_len = tail
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("Invalid number {:?} in duration {:?}", number, str))?;
+        let seconds = match unit {
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 60.0 * 60.0,
+            "" => {
+                return Err(format!(
+                    "Missing unit in duration {:?}, expected one of ms, s, m or h",
+                    str
+                ))
+            }
+            unit => {
+                return Err(format!(
+                    "Unknown unit {:?} in duration {:?}, expected one of ms, s, m or h",
+                    unit, str
+                ))
+            }
+        };
+        total = Duration::try_from_secs_f64(seconds)
+            .ok()
+            .and_then(|part| total.checked_add(part))
+            .ok_or_else(|| format!("Duration {:?} is out of range", str))?;
+        rest = tail.trim_start();
+    }
+    Ok(total)
+}
+
+/// Parses a page refresh interval, rejecting intervals that are zero or smaller than
+/// [`MIN_REFRESH`].
+pub fn parse_refresh(str: &str) -> Result<Duration, String> {
+    let duration = parse_duration(str)?;
+    if duration.is_zero() {
+        Err(format!(
+            "Refresh interval {:?} must be greater than zero, remove it to disable refreshing",
+            str
+        ))
+    } else if duration < MIN_REFRESH {
+        Err(format!(
+            "Refresh interval {:?} is too small, the minimum is {}ms",
+            str,
+            MIN_REFRESH.as_millis()
+        ))
+    } else {
+        Ok(duration)
+    }
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+    assert_eq!(parse_duration("5s"), Ok(Duration::from_secs(5)));
+    assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+    assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+    assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+    assert_eq!(parse_duration("1m30s"), Ok(Duration::from_secs(90)));
+    assert_eq!(
+        parse_duration("1h 0.5m 250ms"),
+        Ok(Duration::from_millis(3_630_250))
+    );
+    assert_eq!(parse_duration(" 10s "), Ok(Duration::from_secs(10)));
+}
+
+#[test]
+fn test_parse_duration_errors() {
+    assert!(parse_duration("").is_err());
+    assert!(parse_duration("5").unwrap_err().contains("Missing unit"));
+    assert!(parse_duration("5d").unwrap_err().contains("Unknown unit"));
+    assert!(parse_duration("s").unwrap_err().contains("Invalid number"));
+    assert!(parse_duration("1.2.3s").is_err());
+    assert!(parse_duration("-5s").is_err());
+    assert!(parse_duration("1e99h").is_err());
+    let overflow = "10000000000000000000s10000000000000000000s";
+    assert!(parse_duration(overflow)
+        .unwrap_err()
+        .contains("out of range"));
+}
+
+#[test]
+fn test_parse_refresh() {
+    assert_eq!(parse_refresh("100ms"), Ok(Duration::from_millis(100)));
+    assert_eq!(parse_refresh("1m30s"), Ok(Duration::from_secs(90)));
+    assert!(parse_refresh("0s")
+        .unwrap_err()
+        .contains("greater than zero"));
+    assert!(parse_refresh("0.0h")
+        .unwrap_err()
+        .contains("greater than zero"));
+    assert!(parse_refresh("10ms").unwrap_err().contains("too small"));
+}
@@ -0,0 +1,61 @@
+//! Escaping of text embedded in generated markup.
+//!
+//! [`escape_html`] makes text and attribute values safe inside HTML, and [`escape_json`] keeps
+//! a page's serialized model from ending the `<script>` element it is embedded in.
+
+/// Escapes `str` for use in HTML text or in a quoted attribute value.
+pub fn escape_html(str: &str) -> String {
+    let mut result = String::with_capacity(str.len());
+    for c in str.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Escapes serialized JSON so it can be embedded in a `<script type="application/json">`
+/// element.
+///
+/// `<`, `>` and `&` can only occur inside JSON strings, where their `\u` escapes decode to the
+/// same value, so the result is still valid JSON that can never close the `<script>` element.
+pub fn escape_json(json: &str) -> String {
+    let mut result = String::with_capacity(json.len());
+    for c in json.chars() {
+        match c {
+            '<' => result.push_str("\\u003c"),
+            '>' => result.push_str("\\u003e"),
+            '&' => result.push_str("\\u0026"),
+            '\u{2028}' => result.push_str("\\u2028"),
+            '\u{2029}' => result.push_str("\\u2029"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[test]
+fn test_escape_html() {
+    assert_eq!(escape_html("Name"), "Name");
+    assert_eq!(
+        escape_html("</th><script>alert('x')</script>"),
+        "&lt;/th&gt;&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"
+    );
+    assert_eq!(escape_html("\" onclick=\"x"), "&quot; onclick=&quot;x");
+    assert_eq!(escape_html("a && b"), "a &amp;&amp; b");
+}
+
+#[test]
+fn test_escape_json() {
+    let json = "{\"name\":\"</script><script>alert(1)</script>\",\"other\":\"a & b \u{2028}\"}";
+    assert_eq!(
+        escape_json(json),
+        "{\"name\":\"\\u003c/script\\u003e\\u003cscript\\u003ealert(1)\\u003c/script\\u003e\",\
+         \"other\":\"a \\u0026 b \\u2028\"}"
+    );
+}
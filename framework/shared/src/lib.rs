@@ -0,0 +1,8 @@
+//! Code used both by the `derive` crate at compile time and by the `framework` crate at runtime.
+//!
+//! It lives in its own crate so the two can not drift apart: markup escaped by the derive and by
+//! the framework is escaped the same way, and `#[page(refresh)]` accepts the same durations as
+//! the `?refresh=` query.
+
+pub mod duration;
+pub mod escape;
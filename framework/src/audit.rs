@@ -0,0 +1,562 @@
+//! An audit log of every action invoked through the framework, see
+//! [`FrameworkBuilder::audit_sink`](crate::FrameworkBuilder::audit_sink).
+//!
+//! The most recent entries can be shown on the built-in `/audit` page, see
+//! [`FrameworkBuilder::audit_page`](crate::FrameworkBuilder::audit_page).
+
+use crate::{Constructor, Request, Result, TableRow};
+use async_std::io::prelude::SeekExt;
+use async_std::io::{ReadExt, SeekFrom, WriteExt};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of entries shown on the `/audit` page.
+pub(crate) const PAGE_ENTRIES: usize = 100;
+
+/// How much of a [`JsonLinesSink`]'s file is read at once, from its end.
+const TAIL_BLOCK: u64 = 64 * 1024;
+
+/// A single invocation of an action.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the action was invoked, in RFC 3339 format and UTC.
+    pub timestamp: String,
+    /// The user that invoked the action, see
+    /// [`FrameworkBuilder::user_header`](crate::FrameworkBuilder::user_header).
+    pub user: Option<String>,
+    /// The URL of the page the action belongs to.
+    pub page: String,
+    pub action: String,
+    /// The [`TableRow::id`]s of the rows the action was invoked for.
+    pub rows: Vec<String>,
+    pub result: AuditResult,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResult {
+    Ok,
+    /// The action started a job, with the URL of its status.
+    Job(String),
+    Error(String),
+    /// A job started by the action succeeded, with the URL of its status.
+    JobSucceeded(String),
+    /// A job started by the action failed.
+    JobFailed {
+        job: String,
+        error: String,
+    },
+}
+
+impl std::fmt::Display for AuditResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::Job(url) => write!(f, "started job {}", url),
+            Self::Error(error) => write!(f, "error: {}", error),
+            Self::JobSucceeded(url) => write!(f, "job {} succeeded", url),
+            Self::JobFailed { job, error } => write!(f, "job {} failed: {}", job, error),
+        }
+    }
+}
+
+/// Stores audit entries, a [`MemorySink`] of the most recent ones is used by default.
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    async fn record(&self, entry: &AuditEntry) -> Result;
+
+    /// The `limit` most recent entries, newest first.
+    ///
+    /// Every entry comes with an id that no other entry of the sink has, and that increases
+    /// in the order the entries were recorded, e.g. a sequence number. It keys the rows of the
+    /// `/audit` page, so identical entries recorded at the same time stay apart.
+    async fn entries(&self, limit: usize) -> Result<Vec<(u64, AuditEntry)>>;
+}
+
+/// Appends every entry as a line of JSON to a file.
+///
+/// The id of an entry is the offset of its line in the file.
+pub struct JsonLinesSink {
+    path: PathBuf,
+    // Appends of different requests should not interleave
+    lock: async_std::sync::Mutex<()>,
+}
+
+impl JsonLinesSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: async_std::sync::Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for JsonLinesSink {
+    async fn record(&self, entry: &AuditEntry) -> Result {
+        let mut line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        line.push('\n');
+        let _lock = self.lock.lock().await;
+        let mut file = async_std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn entries(&self, limit: usize) -> Result<Vec<(u64, AuditEntry)>> {
+        tail(&self.path, limit, TAIL_BLOCK)
+            .await?
+            .iter()
+            .map(|(offset, line)| match serde_json::from_slice(line) {
+                Ok(entry) => Ok((*offset, entry)),
+                Err(e) => Err(e.to_string().into()),
+            })
+            .collect()
+    }
+}
+
+/// The last `limit` non-empty lines of the file at `path` with their offsets, newest first.
+///
+/// The file is read backwards in blocks of `block` bytes, so the log can grow without every
+/// refresh of `/audit` reading all of it.
+async fn tail(path: &Path, limit: usize, block: u64) -> std::io::Result<Vec<(u64, Vec<u8>)>> {
+    let mut file = match async_std::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut position = file.seek(SeekFrom::End(0)).await?;
+    let mut lines = Vec::new();
+    // The part of the file from `position` that was read, of which only the first line can be
+    // incomplete
+    let mut rest = Vec::new();
+    let push = |offset: u64, line: &[u8], lines: &mut Vec<(u64, Vec<u8>)>| {
+        if !line.trim_ascii().is_empty() && lines.len() < limit {
+            lines.push((offset, line.to_vec()));
+        }
+    };
+    while lines.len() < limit && position > 0 {
+        let size = block.min(position);
+        position -= size;
+        file.seek(SeekFrom::Start(position)).await?;
+        let mut read = vec![0; size as usize];
+        file.read_exact(&mut read).await?;
+        read.extend_from_slice(&rest);
+        rest = read;
+        while let Some(newline) = rest.iter().rposition(|&byte| byte == b'\n') {
+            push(
+                position + newline as u64 + 1,
+                &rest[newline + 1..],
+                &mut lines,
+            );
+            rest.truncate(newline);
+        }
+    }
+    if position == 0 {
+        push(0, &rest, &mut lines);
+    }
+    Ok(lines)
+}
+
+/// Keeps entries in memory, e.g. for tests, see [`TestClient`](crate::testing::TestClient).
+///
+/// The default keeps every entry, [`MemorySink::bounded`] only the most recent ones. The id
+/// of an entry is the number of entries recorded before it.
+#[derive(Default)]
+pub struct MemorySink {
+    entries: std::sync::Mutex<MemoryEntries>,
+    capacity: Option<usize>,
+}
+
+#[derive(Default)]
+struct MemoryEntries {
+    entries: VecDeque<(u64, AuditEntry)>,
+    next_id: u64,
+}
+
+impl MemorySink {
+    /// Keeps the `capacity` most recent entries, dropping older ones.
+    pub fn bounded(capacity: usize) -> Self {
+        Self {
+            entries: Default::default(),
+            capacity: Some(capacity),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for MemorySink {
+    async fn record(&self, entry: &AuditEntry) -> Result {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = entries.next_id;
+        entries.next_id += 1;
+        if self.capacity == Some(entries.entries.len()) {
+            entries.entries.pop_front();
+        }
+        if self.capacity != Some(0) {
+            entries.entries.push_back((id, entry.clone()));
+        }
+        Ok(())
+    }
+
+    async fn entries(&self, limit: usize) -> Result<Vec<(u64, AuditEntry)>> {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(entries.entries.iter().rev().take(limit).cloned().collect())
+    }
+}
+
+/// The audit sink of a server, shared with every request through its extensions.
+#[derive(Clone)]
+pub(crate) struct Audit(pub Arc<dyn AuditSink>);
+
+impl Audit {
+    /// Records `entry`, logging instead of failing the action if that is not possible.
+    pub(crate) async fn record(&self, entry: &AuditEntry) {
+        if let Err(e) = self.0.record(entry).await {
+            tracing::error!(error = ?e, ?entry, "Could not record the action in the audit log");
+        }
+    }
+}
+
+/// The action a request invokes, shared through its extensions so the jobs it starts can
+/// record how they ended.
+#[derive(Clone)]
+pub(crate) struct Invocation {
+    pub page: String,
+    pub action: String,
+    pub rows: Vec<String>,
+    /// Closed once the invocation itself is recorded, so the entries of its jobs come after it.
+    pub recorded: async_std::channel::Receiver<()>,
+}
+
+impl Invocation {
+    /// The entry of this invocation by `user`, ending with `result` now.
+    pub(crate) fn entry(&self, user: Option<String>, result: AuditResult) -> AuditEntry {
+        AuditEntry {
+            timestamp: format_timestamp(SystemTime::now()),
+            user,
+            page: self.page.clone(),
+            action: self.action.clone(),
+            rows: self.rows.clone(),
+            result,
+        }
+    }
+}
+
+/// The user of a request, shared through its extensions.
+#[derive(Clone)]
+pub(crate) struct User(pub String);
+
+/// Formats `time` as an RFC 3339 timestamp in UTC, with millisecond precision.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Converts days since the epoch to a date, from Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// The built-in `/audit` page, listing the most recent action invocations.
+#[derive(crate::Page, Serialize, Deserialize, schemars::JsonSchema)]
+#[page(path = "/audit", refresh = "10s")]
+pub struct AuditPage {
+    #[table]
+    #[column(field = "timestamp", header = "Time")]
+    #[column(field = "user", header = "User")]
+    #[column(field = "page", header = "Page")]
+    #[column(field = "action", header = "Action")]
+    #[column(field = "rows", header = "Rows")]
+    #[column(field = "result", header = "Result")]
+    entries: Vec<AuditRow>,
+}
+
+#[async_trait]
+impl<T: Send + Sync + 'static> Constructor<T> for AuditPage {
+    async fn construct(request: Request<T>) -> Result<Self> {
+        let entries = match request.req.ext::<Audit>() {
+            Some(audit) => audit.0.entries(PAGE_ENTRIES).await?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            entries: entries.into_iter().map(AuditRow::from).collect(),
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct AuditRow {
+    /// The id of the entry in its [`AuditSink`]
+    id: u64,
+    timestamp: String,
+    user: String,
+    page: String,
+    action: String,
+    rows: String,
+    result: String,
+}
+
+impl From<(u64, AuditEntry)> for AuditRow {
+    fn from((id, entry): (u64, AuditEntry)) -> Self {
+        Self {
+            id,
+            timestamp: entry.timestamp,
+            user: entry.user.unwrap_or_default(),
+            page: entry.page,
+            action: entry.action,
+            rows: entry.rows.join(", "),
+            result: entry.result.to_string(),
+        }
+    }
+}
+
+impl TableRow for AuditRow {
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+#[test]
+fn test_format_timestamp() {
+    use std::time::Duration;
+    assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    assert_eq!(
+        format_timestamp(UNIX_EPOCH + Duration::from_millis(951_782_400_123)),
+        "2000-02-29T00:00:00.123Z"
+    );
+    assert_eq!(
+        format_timestamp(UNIX_EPOCH + Duration::from_secs(1_792_325_045)),
+        "2026-10-18T12:04:05.000Z"
+    );
+}
+
+#[test]
+fn test_json_lines_sink() {
+    async_std::task::block_on(async {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sink = JsonLinesSink::new(&path);
+        assert!(sink.entries(10).await.unwrap().is_empty());
+
+        let entry = |action: &str, result| AuditEntry {
+            timestamp: format_timestamp(UNIX_EPOCH),
+            user: Some("alice".to_string()),
+            page: "/vms".to_string(),
+            action: action.to_string(),
+            rows: vec!["1".to_string()],
+            result,
+        };
+        let stop = entry("stop", AuditResult::Job("/jobs/1.json".to_string()));
+        let start = entry("start", AuditResult::Error("virsh failed".to_string()));
+        sink.record(&stop).await.unwrap();
+        sink.record(&start).await.unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let second = content.find('\n').unwrap() as u64 + 1;
+        assert_eq!(
+            sink.entries(10).await.unwrap(),
+            [(second, start.clone()), (0, stop)]
+        );
+        assert_eq!(sink.entries(1).await.unwrap(), [(second, start)]);
+
+        assert_eq!(content.lines().count(), 2);
+        assert!(
+            content.contains("\"result\":{\"job\":\"/jobs/1.json\"}"),
+            "{}",
+            content
+        );
+        std::fs::remove_file(&path).unwrap();
+    });
+}
+
+#[test]
+fn test_tail() {
+    async_std::task::block_on(async {
+        let path = std::env::temp_dir().join(format!("tail-{}.jsonl", std::process::id()));
+        std::fs::write(&path, "first\n\nsecond line\nthird\n").unwrap();
+        // Lines that span blocks, and blocks that end in the middle of a line
+        for block in [1, 3, 7, 1024] {
+            let read = |limit| {
+                let path = &path;
+                async move {
+                    let lines = tail(path, limit, block).await.unwrap();
+                    lines
+                        .into_iter()
+                        .map(|(offset, line)| (offset, String::from_utf8(line).unwrap()))
+                        .collect::<Vec<_>>()
+                }
+            };
+            let line = |offset, line: &str| (offset, line.to_string());
+            assert_eq!(read(1).await, [line(19, "third")], "{}", block);
+            assert_eq!(
+                read(2).await,
+                [line(19, "third"), line(7, "second line")],
+                "{}",
+                block
+            );
+            assert_eq!(
+                read(10).await,
+                [line(19, "third"), line(7, "second line"), line(0, "first")],
+                "{}",
+                block
+            );
+            assert!(read(0).await.is_empty());
+        }
+        std::fs::write(&path, "no newline").unwrap();
+        assert_eq!(
+            tail(&path, 5, 4).await.unwrap(),
+            [(0, b"no newline".to_vec())]
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(tail(&path, 5, 4).await.unwrap().is_empty());
+    });
+}
+
+/// Collides with the built-in `/audit` page.
+#[cfg(test)]
+#[derive(crate::Page, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[page(path = "/audit")]
+struct Audits {
+    name: String,
+}
+
+#[test]
+fn test_audit_page_collision() {
+    let builder = crate::FrameworkBuilder::default()
+        .add_page::<Audits>()
+        .audit_page(true);
+    let error = crate::testing::TestClient::new(builder).err().unwrap();
+    assert!(format!("{:?}", error).contains("/audit"), "{:?}", error);
+
+    async_std::task::block_on(async {
+        // The page is not served by default
+        let builder = crate::FrameworkBuilder::default().add_page::<Audits>();
+        let client = crate::testing::TestClient::new(builder).unwrap();
+        assert_eq!(client.get("/audit.json").await.json()["name"], "");
+
+        // A configuration without `[audit] page` keeps the builder's choice
+        let builder = crate::FrameworkBuilder::default()
+            .audit_page(true)
+            .config(&crate::Config::default());
+        let client = crate::testing::TestClient::new(builder).unwrap();
+        assert_eq!(client.get("/audit").await.status(), 200);
+    });
+}
+
+#[test]
+fn test_memory_sink() {
+    async_std::task::block_on(async {
+        let entry = |action: &str| AuditEntry {
+            timestamp: format_timestamp(UNIX_EPOCH),
+            user: None,
+            page: "/vms".to_string(),
+            action: action.to_string(),
+            rows: Vec::new(),
+            result: AuditResult::Ok,
+        };
+        let sink = MemorySink::bounded(2);
+        for action in ["start", "stop", "reboot"] {
+            sink.record(&entry(action)).await.unwrap();
+        }
+        assert_eq!(
+            sink.entries(10).await.unwrap(),
+            [(2, entry("reboot")), (1, entry("stop"))]
+        );
+
+        // Identical entries, e.g. of a double-clicked action, are rows of their own on `/audit`
+        sink.record(&entry("reboot")).await.unwrap();
+        let entries = sink.entries(2).await.unwrap();
+        let rows: Vec<_> = entries.into_iter().map(AuditRow::from).collect();
+        assert_ne!(rows[0].id(), rows[1].id());
+
+        let sink = MemorySink::bounded(0);
+        sink.record(&entry("start")).await.unwrap();
+        assert!(sink.entries(10).await.unwrap().is_empty());
+    });
+}
+
+/// Starts a backup job that fails.
+#[cfg(test)]
+#[derive(crate::Page, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[page(path = "/backups")]
+struct Backups {
+    #[table(selectable)]
+    #[bulk_action(name = "Back up", fn = "back_up")]
+    disks: Vec<Disk>,
+}
+
+#[cfg(test)]
+#[derive(Clone, Serialize, Deserialize, schemars::JsonSchema)]
+struct Disk {
+    name: String,
+}
+
+#[cfg(test)]
+impl TableRow for Disk {
+    fn id(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[cfg(test)]
+impl Backups {
+    async fn back_up(&mut self, request: Request<()>, _: Vec<Disk>) -> Result<crate::Job> {
+        request.spawn_job("Back up", |_| async { Err("disk full".to_string().into()) })
+    }
+}
+
+#[test]
+fn test_audit_failed_job() {
+    async_std::task::block_on(async {
+        let builder = crate::FrameworkBuilder::default().add_page::<Backups>();
+        let client = crate::testing::TestClient::new(builder).unwrap();
+        let started = client.action("/backups", "back_up", &[]).await;
+        assert_eq!(started.status(), 202);
+        let job = client.wait_for_job(&started).await;
+        assert_eq!(job.json()["state"], "failed");
+
+        let url = started.json()["job"].as_str().unwrap().to_string();
+        let log = client.audit_log().await;
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[1].result, AuditResult::Job(url.clone()));
+        assert_eq!(log[0].action, "back_up");
+        assert!(
+            matches!(&log[0].result, AuditResult::JobFailed { job, error }
+                if *job == url && error.contains("disk full")),
+            "{:?}",
+            log[0].result
+        );
+    });
+}
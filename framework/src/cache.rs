@@ -0,0 +1,299 @@
+//! Reuses constructed pages for `#[page(cache = "2s")]`, so many clients refreshing the same
+//! page share a single construction.
+
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The constructed pages of one page type, by cache key.
+///
+/// Every key has its own async lock, held while the page is constructed, so concurrent requests
+/// for the same key wait for that construction instead of starting their own.
+pub(crate) struct PageCache<P> {
+    slots: Mutex<HashMap<String, Arc<Slot<P>>>>,
+}
+
+struct Slot<P> {
+    state: async_std::sync::Mutex<State<P>>,
+    /// How many constructions finished, so waiters can tell whether one finished while they
+    /// waited.
+    attempts: AtomicUsize,
+}
+
+impl<P> Default for Slot<P> {
+    fn default() -> Self {
+        Self {
+            state: async_std::sync::Mutex::new(State {
+                entry: None,
+                failure: None,
+            }),
+            attempts: AtomicUsize::new(0),
+        }
+    }
+}
+
+struct State<P> {
+    entry: Option<Entry<P>>,
+    /// The error of the last construction, if it failed.
+    failure: Option<Error>,
+}
+
+struct Entry<P> {
+    constructed: Instant,
+    page: Arc<P>,
+}
+
+impl<P> Default for PageCache<P> {
+    fn default() -> Self {
+        Self {
+            slots: Mutex::default(),
+        }
+    }
+}
+
+impl<P> PageCache<P> {
+    /// The page cached for `key` if it was constructed less than `ttl` ago, otherwise the result
+    /// of `construct`, which is cached if it succeeds.
+    ///
+    /// A failed construction is not cached, but the requests that waited for it get its error
+    /// instead of all constructing the page again.
+    pub(crate) async fn get_or_construct(
+        &self,
+        key: String,
+        ttl: Duration,
+        construct: impl Future<Output = Result<P>>,
+    ) -> Result<Arc<P>> {
+        let slot = {
+            let mut slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+            if !slots.contains_key(&key) {
+                // Keys can come from query parameters, so do not keep expired ones around
+                slots.retain(|_, slot| match slot.state.try_lock() {
+                    Some(state) => state
+                        .entry
+                        .as_ref()
+                        .is_some_and(|entry| entry.constructed.elapsed() < ttl),
+                    None => true,
+                });
+            }
+            slots.entry(key).or_default().clone()
+        };
+        let attempts = slot.attempts.load(Ordering::SeqCst);
+        let mut state = slot.state.lock().await;
+        if let Some(entry) = state.entry.as_ref() {
+            if entry.constructed.elapsed() < ttl {
+                return Ok(entry.page.clone());
+            }
+        }
+        if slot.attempts.load(Ordering::SeqCst) != attempts {
+            if let Some(failure) = &state.failure {
+                return Err(copy(failure));
+            }
+        }
+        let result = construct.await;
+        slot.attempts.fetch_add(1, Ordering::SeqCst);
+        match result {
+            Ok(page) => {
+                let page = Arc::new(page);
+                state.entry = Some(Entry {
+                    constructed: Instant::now(),
+                    page: page.clone(),
+                });
+                state.failure = None;
+                Ok(page)
+            }
+            Err(e) => {
+                state.failure = Some(copy(&e));
+                Err(e)
+            }
+        }
+    }
+
+    /// Forgets every cached page, e.g. after an action changed them.
+    pub(crate) fn clear(&self) {
+        self.slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+/// A copy of `error` for another request, keeping the status it responds with.
+fn copy(error: &Error) -> Error {
+    match error {
+        Error::NotFound(message) => Error::NotFound(message.clone()),
+        Error::Conflict(message) => Error::Conflict(message.clone()),
+        Error::String(message) => Error::String(message.clone()),
+        error => Error::String(format!("{:?}", error)),
+    }
+}
+
+#[test]
+fn test_cache_coalesces_constructions() {
+    async_std::task::block_on(async {
+        let cache = Arc::new(PageCache::<usize>::default());
+        let constructions = Arc::new(AtomicUsize::new(0));
+        let get = |key: &str| {
+            let (cache, constructions, key) =
+                (cache.clone(), constructions.clone(), key.to_string());
+            async_std::task::spawn(async move {
+                let construct = async {
+                    async_std::task::sleep(Duration::from_millis(50)).await;
+                    Ok(constructions.fetch_add(1, Ordering::SeqCst))
+                };
+                *cache
+                    .get_or_construct(key, Duration::from_secs(60), construct)
+                    .await
+                    .unwrap()
+            })
+        };
+
+        let concurrent: Vec<_> = (0..10).map(|_| get("a")).collect();
+        for page in concurrent {
+            assert_eq!(page.await, 0);
+        }
+        assert_eq!(get("a").await, 0);
+        assert_eq!(get("b").await, 1);
+        cache.clear();
+        assert_eq!(get("a").await, 2);
+        assert_eq!(constructions.load(Ordering::SeqCst), 3);
+
+        let failed = cache
+            .get_or_construct("c".to_string(), Duration::from_secs(60), async {
+                Err("unreachable".to_string().into())
+            })
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(get("c").await, 3);
+    });
+}
+
+#[test]
+fn test_cache_coalesces_failures() {
+    async_std::task::block_on(async {
+        let cache = Arc::new(PageCache::<()>::default());
+        let constructions = Arc::new(AtomicUsize::new(0));
+        let get = || {
+            let (cache, constructions) = (cache.clone(), constructions.clone());
+            async_std::task::spawn(async move {
+                let construct = async {
+                    async_std::task::sleep(Duration::from_millis(50)).await;
+                    constructions.fetch_add(1, Ordering::SeqCst);
+                    Err(Error::NotFound("down".to_string()))
+                };
+                cache
+                    .get_or_construct("a".to_string(), Duration::from_secs(60), construct)
+                    .await
+            })
+        };
+
+        let concurrent: Vec<_> = (0..10).map(|_| get()).collect();
+        for result in concurrent {
+            assert!(matches!(result.await, Err(Error::NotFound(e)) if e == "down"));
+        }
+        assert_eq!(constructions.load(Ordering::SeqCst), 1);
+        // Failures are not cached for later requests
+        assert!(get().await.is_err());
+        assert_eq!(constructions.load(Ordering::SeqCst), 2);
+    });
+}
+
+#[test]
+fn test_cache_expires() {
+    async_std::task::block_on(async {
+        let cache = PageCache::default();
+        let ttl = Duration::from_millis(20);
+        let first = cache.get_or_construct("a".to_string(), ttl, async { Ok(1) });
+        assert_eq!(*first.await.unwrap(), 1);
+        let cached = cache.get_or_construct("a".to_string(), ttl, async { Ok(2) });
+        assert_eq!(*cached.await.unwrap(), 1);
+        async_std::task::sleep(ttl).await;
+        let expired = cache.get_or_construct("a".to_string(), ttl, async { Ok(3) });
+        assert_eq!(*expired.await.unwrap(), 3);
+    });
+}
+
+#[cfg(test)]
+static CONSTRUCTED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Counts its constructions, and is cached per user.
+#[cfg(test)]
+#[derive(crate::Page, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[page(path = "/cached", cache = "1m", cache_per_user)]
+struct Cached {
+    constructed: usize,
+    user: Option<String>,
+    #[table(selectable)]
+    #[bulk_action(name = "Touch", fn = "touch")]
+    rows: Vec<CachedRow>,
+}
+
+#[cfg(test)]
+#[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+struct CachedRow {
+    id: String,
+}
+
+#[cfg(test)]
+impl crate::TableRow for CachedRow {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+}
+
+#[cfg(test)]
+impl Cached {
+    async fn touch(&mut self, _: crate::Request<()>, _: Vec<CachedRow>) -> Result {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl crate::Constructor for Cached {
+    async fn construct(request: crate::Request<()>) -> Result<Self> {
+        async_std::task::sleep(Duration::from_millis(50)).await;
+        let constructed = CONSTRUCTED.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(Self {
+            constructed,
+            user: request.user(),
+            rows: Vec::new(),
+        })
+    }
+}
+
+#[test]
+fn test_cached_page() {
+    async_std::task::block_on(async {
+        let builder = crate::FrameworkBuilder::default()
+            .add_page::<Cached>()
+            .user_header("X-User");
+        let alice = crate::testing::TestClient::new(builder)
+            .unwrap()
+            .header("X-User", "alice");
+        let concurrent = futures_lite::future::zip(
+            alice.get("/cached.json"),
+            alice.get("/cached.json?refresh=off"),
+        );
+        let (first, second) = concurrent.await;
+        assert_eq!(first.json(), second.json());
+        let html = alice.get("/cached").await;
+        assert!(html.text().contains("alice"), "{}", html.text());
+        assert_eq!(CONSTRUCTED.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // Other users and queries get their own page
+        let mut bob = alice.request(tide::http::Method::Get, "/cached.json");
+        bob.insert_header("X-User", "bob");
+        assert_eq!(alice.send(bob).await.json()["user"], "bob");
+        let other_query = alice.get("/cached.json?host=b").await;
+        assert_eq!(other_query.json()["user"], "alice");
+        assert_eq!(CONSTRUCTED.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+        // Actions construct a fresh page, and invalidate the cached ones
+        let touched = alice.action("/cached", "touch", &[]).await;
+        assert_eq!(touched.json()["model"]["constructed"], 3);
+        assert_eq!(alice.get("/cached.json").await.json()["constructed"], 4);
+    });
+}
@@ -0,0 +1,222 @@
+//! Typed clients for the pages of another service, enabled by the `client` feature.
+//!
+//! `#[derive(Page)]` then also generates a `<Page>Client`, e.g. for `Vms`:
+//!
+//! ```ignore
+//! let client = VmsClient::new("http://vms.internal:8080")?;
+//! let vms: Vms = client.fetch().await?;
+//! let result = client.turn_on(&vms.vms[0]).await?;
+//! assert!(result.job.is_none());
+//! ```
+
+use crate::{Error, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// The HTTP client behind every generated `<Page>Client`.
+#[derive(Clone, Debug)]
+pub struct Client {
+    base_url: String,
+    authorization: Option<String>,
+    http: surf::Client,
+}
+
+/// The response to an action: the model of the page after the action, and the URL of the
+/// status of the [`Job`](crate::Job) it started, if any.
+#[derive(Debug, Deserialize)]
+pub struct ActionResult<P> {
+    pub model: P,
+    pub job: Option<String>,
+}
+
+/// The row type of a `#[table]` field, so generated action methods take the rows they act on.
+#[doc(hidden)]
+pub trait Rows {
+    type Row;
+}
+
+impl<R> Rows for Vec<R> {
+    type Row = R;
+}
+
+impl Client {
+    /// A client for the service at `base_url`, e.g. `http://localhost:8080`.
+    pub fn new(base_url: &str) -> Result<Self> {
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            return Err(format!("Expected an http(s):// URL, found {:?}", base_url).into());
+        }
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            authorization: None,
+            http: surf::Client::new(),
+        })
+    }
+
+    /// Logs in with every request, see
+    /// [`FrameworkBuilder::basic_auth`](crate::FrameworkBuilder::basic_auth).
+    pub fn basic_auth(mut self, user: &str, password: &str) -> Self {
+        let auth = tide::http::auth::BasicAuth::new(user, password);
+        self.authorization = Some(auth.value().to_string());
+        self
+    }
+
+    /// The model of the page at `url`, from `<url>.json`.
+    pub async fn fetch<P: DeserializeOwned>(&self, url: &str) -> Result<P> {
+        let request = self.http.get(format!("{}{}.json", self.base_url, url));
+        self.send(request).await
+    }
+
+    /// Invokes the action `function` of the page at `url`, for the rows with the
+    /// [`TableRow::id`](crate::TableRow::id)s `rows`.
+    pub async fn invoke<P: DeserializeOwned>(
+        &self,
+        url: &str,
+        function: &str,
+        rows: Vec<String>,
+    ) -> Result<ActionResult<P>> {
+        let request = self
+            .http
+            .post(format!("{}{}/actions/{}", self.base_url, url, function))
+            .body_json(&serde_json::json!({ "rows": rows }))
+            .map_err(|e| Error::String(e.to_string()))?;
+        self.send(request).await
+    }
+
+    /// Sends `request`, mapping error responses to the [`Error`] the server responded with.
+    async fn send<R: DeserializeOwned>(&self, mut request: surf::RequestBuilder) -> Result<R> {
+        if let Some(authorization) = &self.authorization {
+            request = request.header("Authorization", authorization.as_str());
+        }
+        let request = request.build();
+        let url = request.url().to_string();
+        let mut response = self
+            .http
+            .send(request)
+            .await
+            .map_err(|e| Error::String(format!("{}: {}", url, e)))?;
+        let body = response
+            .body_string()
+            .await
+            .map_err(|e| Error::String(format!("{}: {}", url, e)))?;
+        if response.status().is_success() {
+            return serde_json::from_str(&body)
+                .map_err(|e| Error::String(format!("Invalid response from {}: {}", url, e)));
+        }
+        let message = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|json| json["error"].as_str().map(str::to_string))
+            .unwrap_or(body);
+        Err(match response.status() as u16 {
+            404 => Error::NotFound(message),
+            409 => Error::Conflict(message),
+            status => Error::String(format!("{} from {}: {}", status, url, message)),
+        })
+    }
+}
+
+#[cfg(test)]
+#[derive(
+    crate::Page, Clone, Debug, PartialEq, serde::Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[page(path = "/doors")]
+struct Doors {
+    #[table(selectable)]
+    #[column(field = "name")]
+    #[action(name = "Open", fn = "open", enabled_if = "is_closed")]
+    #[bulk_action(name = "Close", fn = "close")]
+    doors: Vec<Door>,
+}
+
+#[cfg(test)]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, Deserialize, schemars::JsonSchema)]
+struct Door {
+    name: String,
+    open: bool,
+}
+
+#[cfg(test)]
+impl Door {
+    fn is_closed(&self) -> bool {
+        !self.open
+    }
+}
+
+#[cfg(test)]
+impl crate::TableRow for Door {
+    fn id(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl crate::Constructor for Doors {
+    async fn construct(_: crate::Request<()>) -> Result<Self> {
+        let door = |name: &str, open| Door {
+            name: name.to_string(),
+            open,
+        };
+        Ok(Self {
+            doors: vec![door("front", false), door("back", true)],
+        })
+    }
+}
+
+#[cfg(test)]
+impl Doors {
+    async fn open(&mut self, _: crate::Request<()>, row: Door) -> Result {
+        for door in &mut self.doors {
+            door.open |= door.name == row.name;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self, _: crate::Request<()>, rows: Vec<Door>) -> Result {
+        for door in &mut self.doors {
+            door.open &= !rows.contains(door);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_client() {
+    async_std::task::block_on(async {
+        let server = crate::FrameworkBuilder::default()
+            .add_page::<Doors>()
+            .basic_auth([("alice".to_string(), "secret".to_string())])
+            .audit_sink(crate::MemorySink::default())
+            .bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = server.addresses().remove(0);
+        let shutdown = server.shutdown_handle();
+        let running = async_std::task::spawn(server.run());
+
+        let client = DoorsClient::new(&address).unwrap();
+        assert!(matches!(client.fetch().await, Err(Error::String(e)) if e.starts_with("401")));
+
+        let client = DoorsClient::new(&address)
+            .unwrap()
+            .basic_auth("alice", "secret");
+        let doors = client.fetch().await.unwrap();
+        assert_eq!(doors.doors[0].name, "front");
+
+        let opened = client.open(&doors.doors[0]).await.unwrap();
+        assert!(opened.model.doors[0].open);
+        assert_eq!(opened.job, None);
+        let closed = client.close(&doors.doors).await.unwrap();
+        assert!(closed.model.doors.iter().all(|door| !door.open));
+        // The back door is already open
+        let conflict = client.open(&doors.doors[1]).await;
+        assert!(
+            matches!(conflict, Err(Error::Conflict(_))),
+            "{:?}",
+            conflict
+        );
+
+        assert!(DoorsClient::new("localhost:8080").is_err());
+        shutdown.shutdown();
+        running.await.unwrap();
+    });
+}
@@ -0,0 +1,584 @@
+//! Settings of an app, loaded from a TOML file and `WEB_*` environment variables, see
+//! [`Config::load`] and [`FrameworkBuilder::run_config`](crate::FrameworkBuilder::run_config).
+
+use crate::{duration, Result};
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{DeserializeOwned, IntoDeserializer, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The prefix of environment variables that override the configuration file.
+pub const ENV_PREFIX: &str = "WEB_";
+
+/// The settings of an app.
+///
+/// ```toml
+/// bind = ["0.0.0.0:443", "[::]:443"]
+/// log = "info,framework=debug"
+///
+/// [tls]
+/// cert = "cert.pem"
+/// key = "key.pem"
+/// redirect_http = "0.0.0.0:80"
+///
+/// [users]
+/// alice = "correct horse battery staple"
+///
+/// [refresh]
+/// "/vms" = "10s"
+/// "/uptime" = "off"
+///
+/// [audit]
+/// path = "/var/log/web/audit.jsonl"
+/// page = true
+///
+/// # Sections of the app itself, see `Config::section`
+/// [vms]
+/// connect = "qemu:///system"
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The addresses to listen on, `["localhost:8080"]` by default. A single address can also
+    /// be given as a string.
+    #[serde(deserialize_with = "one_or_many")]
+    pub bind: Vec<String>,
+    /// Serves HTTPS instead of HTTP when set.
+    pub tls: Option<TlsConfig>,
+    /// Passwords by user name, see
+    /// [`FrameworkBuilder::basic_auth`](crate::FrameworkBuilder::basic_auth).
+    pub users: BTreeMap<String, String>,
+    pub assets: AssetsConfig,
+    /// Refresh intervals by page URL, like `"5s"` or `"off"`, see
+    /// [`FrameworkBuilder::refresh`](crate::FrameworkBuilder::refresh).
+    #[serde(deserialize_with = "refresh_intervals")]
+    pub refresh: BTreeMap<String, Option<Duration>>,
+    pub audit: AuditConfig,
+    /// The default filter of the app's `tracing` subscriber, e.g. `"info,framework=debug"`.
+    pub log: String,
+    // Every other section, so apps can add their own
+    #[serde(skip)]
+    sections: toml::Table,
+}
+
+/// The keys of [`Config`], every other table is a section of the app.
+const KEYS: &[&str] = &["bind", "tls", "users", "assets", "refresh", "audit", "log"];
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec!["localhost:8080".to_string()],
+            tls: None,
+            users: BTreeMap::new(),
+            assets: AssetsConfig::default(),
+            refresh: BTreeMap::new(),
+            audit: AuditConfig::default(),
+            log: "info".to_string(),
+            sections: toml::Table::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// A PEM file with the certificate chain.
+    pub cert: PathBuf,
+    /// A PEM file with the private key.
+    pub key: PathBuf,
+    /// See [`FrameworkBuilder::redirect_http`](crate::FrameworkBuilder::redirect_http).
+    pub redirect_http: Option<String>,
+}
+
+/// The script and stylesheets every page loads, see
+/// [`FrameworkBuilder::assets`](crate::FrameworkBuilder::assets).
+#[derive(Clone, Debug)]
+pub struct Assets {
+    /// The binding runtime, `/static/script.js` by default.
+    pub script: String,
+    /// `["/static/style.css"]` by default, add stylesheets after it to restyle pages.
+    pub stylesheets: Vec<String>,
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self {
+            script: "/static/script.js".to_string(),
+            stylesheets: vec!["/static/style.css".to_string()],
+        }
+    }
+}
+
+/// Overrides of the [`Assets`] of the app, the ones that are not set are kept.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssetsConfig {
+    pub script: Option<String>,
+    pub stylesheets: Option<Vec<String>>,
+}
+
+/// Where the audit log of every invoked action is stored, and whether it is shown.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// The file the entries are appended to, see [`JsonLinesSink`](crate::JsonLinesSink). When
+    /// not set, only the most recent entries are kept in memory.
+    pub path: Option<PathBuf>,
+    /// Whether the most recent entries are shown at `/audit`, overriding
+    /// [`FrameworkBuilder::audit_page`](crate::FrameworkBuilder::audit_page) when set.
+    pub page: Option<bool>,
+}
+
+impl Config {
+    /// Loads the TOML file at `path`, if it exists, and overrides it with the `WEB_*`
+    /// environment variables.
+    ///
+    /// A double underscore separates the keys of nested sections, e.g. `WEB_TLS__CERT` sets
+    /// `cert` in `[tls]`. Values are strings, which are parsed like TOML values where a number,
+    /// boolean or list is expected, so `WEB_USERS__BOB=1234` is a password and
+    /// `WEB_BIND='["0.0.0.0:80", "[::]:80"]'` a list.
+    ///
+    /// Keys that are neither settings of the framework nor sections are rejected, to catch
+    /// typos.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let toml = match std::fs::read_to_string(path) {
+            Ok(toml) => toml,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Self::parse(&toml, std::env::vars())
+            .map_err(|e| format!("Invalid configuration {}: {}", path.display(), e).into())
+    }
+
+    /// Parses `toml` and overrides it with the variables in `env` that start with `WEB_`, see
+    /// [`load`](Self::load).
+    pub fn parse(
+        toml: &str,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> std::result::Result<Self, String> {
+        let mut table: toml::Table = toml.parse().map_err(|e| format!("{}", e))?;
+        for (name, value) in env {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                set_env_override(&mut table, key, &value)
+                    .map_err(|e| format!("{} in {}", e, name))?;
+            }
+        }
+        let (settings, sections) = table
+            .into_iter()
+            .partition::<toml::Table, _>(|(key, _)| KEYS.contains(&key.as_str()));
+        if let Some((key, _)) = sections.iter().find(|(_, value)| !value.is_table()) {
+            return Err(format!("Unknown setting {:?}", key));
+        }
+        let mut config = Config::deserialize(Coerce(toml::Value::Table(settings)))
+            .map_err(|e| format!("{}", e))?;
+        config.sections = sections;
+        Ok(config)
+    }
+
+    /// Deserializes the app-specific section `[name]`. A missing section is deserialized from
+    /// an empty table, so sections whose fields all have defaults are optional.
+    pub fn section<S: DeserializeOwned>(&self, name: &str) -> Result<S> {
+        let section = match self.sections.get(name) {
+            Some(section) => section.clone(),
+            None => toml::Value::Table(toml::Table::new()),
+        };
+        S::deserialize(Coerce(section))
+            .map_err(|e| format!("Invalid [{}] section: {}", name, e).into())
+    }
+}
+
+/// Sets the value at the `__` separated `key` in `table`.
+fn set_env_override(
+    mut table: &mut toml::Table,
+    key: &str,
+    value: &str,
+) -> std::result::Result<(), String> {
+    let path: Vec<String> = key.split("__").map(str::to_lowercase).collect();
+    let (last, parents) = path.split_last().expect("split returns at least one part");
+    for parent in parents {
+        let entry = table
+            .entry(parent.as_str())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        table = match entry {
+            toml::Value::Table(table) => table,
+            _ => return Err(format!("{} is not a section", parent)),
+        };
+    }
+    // Typed when deserialized, see `Coerce`
+    table.insert(last.clone(), toml::Value::String(value.to_string()));
+    Ok(())
+}
+
+/// Parses `value` like the value of a TOML key, e.g. `5` or `["a", "b"]`.
+fn parse_value(value: &str) -> Option<toml::Value> {
+    format!("value = {}", value)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+}
+
+/// Deserializes a TOML value, parsing strings with [`parse_value`] where a number, boolean or
+/// list is expected. Environment variables can only set strings, which keeps a password like
+/// `WEB_USERS__BOB=1234` a string while `WEB_VMS__LIMIT=5` still sets a number.
+struct Coerce(toml::Value);
+
+impl Coerce {
+    fn typed(self) -> Self {
+        match &self.0 {
+            toml::Value::String(value) => parse_value(value).map_or(self, Self),
+            _ => self,
+        }
+    }
+}
+
+macro_rules! typed {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Self::Error> {
+            self.typed().deserialize_any(visitor)
+        }
+    )*};
+}
+
+impl<'de> Deserializer<'de> for Coerce {
+    type Error = toml::de::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        match self.0 {
+            toml::Value::Table(table) => {
+                let entries = table.into_iter().map(|(key, value)| (key, Coerce(value)));
+                visitor.visit_map(MapDeserializer::new(entries))
+            }
+            toml::Value::Array(array) => {
+                visitor.visit_seq(SeqDeserializer::new(array.into_iter().map(Coerce)))
+            }
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    typed! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_seq
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _: usize,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        self.typed().deserialize_any(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        self.typed().deserialize_any(visitor)
+    }
+
+    // TOML has no null, so a value that is there is always `Some`
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        self.0.deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, toml::de::Error> for Coerce {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        // A list from an environment variable, addresses like `[::]:80` are not valid TOML
+        OneOrMany::One(one) => match parse_value(&one).map(toml::Value::try_into) {
+            Some(Ok(many)) => many,
+            _ => vec![one],
+        },
+        OneOrMany::Many(many) => many,
+    })
+}
+
+fn refresh_intervals<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<String, Option<Duration>>, D::Error> {
+    let intervals = BTreeMap::<String, String>::deserialize(deserializer)?;
+    intervals
+        .into_iter()
+        .map(|(url, interval)| {
+            // Environment variables can not contain `/`, so `WEB_REFRESH__VMS` means `/vms`
+            let url = if url.starts_with('/') {
+                url
+            } else {
+                format!("/{}", url)
+            };
+            let interval = match interval.as_str() {
+                "off" => None,
+                interval => {
+                    Some(duration::parse_refresh(interval).map_err(serde::de::Error::custom)?)
+                }
+            };
+            Ok((url, interval))
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_config() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Vms {
+        connect: String,
+        #[serde(default)]
+        limit: u32,
+    }
+
+    let toml = r#"
+        bind = "0.0.0.0:8080"
+
+        [tls]
+        cert = "cert.pem"
+        key = "key.pem"
+
+        [refresh]
+        "/vms" = "10s"
+        uptime = "off"
+
+        [audit]
+        page = false
+
+        [vms]
+        connect = "qemu:///system"
+    "#;
+    let env = [
+        ("WEB_TLS__REDIRECT_HTTP", "0.0.0.0:80"),
+        ("WEB_USERS__ALICE", "secret"),
+        ("WEB_USERS__BOB", "1234"),
+        ("WEB_USERS__CAROL", "true"),
+        ("WEB_ASSETS__STYLESHEETS", "[\"/a.css\", \"/b.css\"]"),
+        ("WEB_VMS__LIMIT", "5"),
+        ("WEB_LOG", "debug"),
+        ("WEB_AUDIT__PATH", "/var/log/web/audit.jsonl"),
+        ("PATH", "/usr/bin"),
+    ]
+    .map(|(name, value)| (name.to_string(), value.to_string()));
+    let config = Config::parse(toml, env).unwrap();
+
+    assert_eq!(config.bind, ["0.0.0.0:8080"]);
+    let tls = config.tls.as_ref().unwrap();
+    assert_eq!(tls.cert, Path::new("cert.pem"));
+    assert_eq!(tls.redirect_http.as_deref(), Some("0.0.0.0:80"));
+    assert_eq!(config.users["alice"], "secret");
+    assert_eq!(config.users["bob"], "1234");
+    assert_eq!(config.users["carol"], "true");
+    assert_eq!(config.refresh["/vms"], Some(Duration::from_secs(10)));
+    assert_eq!(config.refresh["/uptime"], None);
+    assert_eq!(config.log, "debug");
+    assert_eq!(
+        config.audit.path.as_deref(),
+        Some(Path::new("/var/log/web/audit.jsonl"))
+    );
+    assert_eq!(config.audit.page, Some(false));
+    assert_eq!(config.assets.script, None);
+    assert_eq!(
+        config.assets.stylesheets.as_deref(),
+        Some(&["/a.css".to_string(), "/b.css".to_string()][..])
+    );
+    assert_eq!(
+        config.section::<Vms>("vms").unwrap(),
+        Vms {
+            connect: "qemu:///system".to_string(),
+            limit: 5
+        }
+    );
+    assert!(config.section::<Vms>("missing").is_err());
+}
+
+#[test]
+fn test_config_errors() {
+    let parse = |toml: &str, env: &[(&str, &str)]| {
+        let env = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        Config::parse(toml, env).unwrap_err()
+    };
+    let error = parse("[refresh]\n\"/vms\" = \"fast\"", &[]);
+    assert!(error.contains("fast"), "{}", error);
+    let error = parse("[tls]\ncert = \"cert.pem\"", &[]);
+    assert!(error.contains("key"), "{}", error);
+    let error = parse("log = \"info\"", &[("WEB_LOG__LEVEL", "debug")]);
+    assert!(error.contains("WEB_LOG__LEVEL"), "{}", error);
+    let error = parse("bnid = \"0.0.0.0:80\"", &[]);
+    assert!(error.contains("bnid"), "{}", error);
+    let error = parse("", &[("WEB_BNID", "0.0.0.0:80")]);
+    assert!(error.contains("bnid"), "{}", error);
+    let error = parse("[audit]\npage = \"maybe\"", &[]);
+    assert!(error.contains("maybe"), "{}", error);
+    let error = parse(
+        "[tls]\ncert = \"cert.pem\"\nkey = \"key.pem\"\nhost = \"a\"",
+        &[],
+    );
+    assert!(error.contains("host"), "{}", error);
+
+    let config = Config::parse("", []).unwrap();
+    assert_eq!(config.bind, ["localhost:8080"]);
+    assert!(config.users.is_empty());
+    assert_eq!(config.audit.path, None);
+    assert_eq!(config.audit.page, None);
+}
+
+#[test]
+fn test_env_values() {
+    let parse = |env: &[(&str, &str)]| {
+        let env = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        Config::parse("", env).unwrap()
+    };
+    let config = parse(&[("WEB_BIND", "[\"0.0.0.0:80\", \"[::]:80\"]")]);
+    assert_eq!(config.bind, ["0.0.0.0:80", "[::]:80"]);
+    let config = parse(&[("WEB_BIND", "[::]:80"), ("WEB_AUDIT__PAGE", "true")]);
+    assert_eq!(config.bind, ["[::]:80"]);
+    assert_eq!(config.audit.page, Some(true));
+    // Strings that look like other TOML values stay strings
+    let config = parse(&[("WEB_LOG", "1979-05-27"), ("WEB_USERS__ADMIN", "12.5")]);
+    assert_eq!(config.log, "1979-05-27");
+    assert_eq!(config.users["admin"], "12.5");
+}
+
+#[test]
+fn test_config_keeps_unset_assets() {
+    async_std::task::block_on(async {
+        let config = Config::parse(
+            "[assets]
+script = \"/app.js\"",
+            [],
+        )
+        .unwrap();
+        let builder = crate::FrameworkBuilder::default()
+            .audit_page(true)
+            .assets(Assets {
+                script: "/static/script.js".to_string(),
+                stylesheets: vec!["/static/style.css".to_string(), "/custom.css".to_string()],
+            })
+            .config(&config);
+        let client = crate::testing::TestClient::new(builder).unwrap();
+        let page = client.get("/audit").await;
+        assert!(page.text().contains("/app.js"), "{}", page.text());
+        assert!(page.text().contains("/custom.css"), "{}", page.text());
+    });
+}
+
+#[test]
+fn test_bind_config() {
+    async_std::task::block_on(async {
+        let config = Config::parse(
+            r#"
+            bind = "127.0.0.1:0"
+            users = { alice = "secret" }
+            audit = { page = true }
+            assets = { stylesheets = ["/static/style.css", "/custom.css"] }
+            "#,
+            [],
+        )
+        .unwrap();
+        let server = crate::FrameworkBuilder::default()
+            .bind_config(&config)
+            .await
+            .unwrap();
+        let address = server.addresses().remove(0);
+        let shutdown = server.shutdown_handle();
+        let running = async_std::task::spawn(server.run());
+
+        let response = crate::server::get(
+            crate::server::connect(&address).await,
+            "localhost",
+            "/audit",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+        assert!(response.contains("www-authenticate: Basic"), "{}", response);
+
+        let get = |credentials: &'static str| {
+            let address = address.clone();
+            async move {
+                let mut stream = crate::server::connect(&address).await;
+                let request = format!(
+                    "GET /audit HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic {}\r\nConnection: close\r\n\r\n",
+                    credentials
+                );
+                async_std::io::WriteExt::write_all(&mut stream, request.as_bytes())
+                    .await
+                    .unwrap();
+                let mut response = String::new();
+                async_std::io::ReadExt::read_to_string(&mut stream, &mut response)
+                    .await
+                    .unwrap();
+                response
+            }
+        };
+        // alice:wrong
+        let response = get("YWxpY2U6d3Jvbmc=").await;
+        assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+        // alice:secret
+        let response = get("YWxpY2U6c2VjcmV0").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(
+            response.contains("<link rel='stylesheet' href='/custom.css'>"),
+            "{}",
+            response
+        );
+
+        shutdown.shutdown();
+        running.await.unwrap();
+
+        let config = Config::parse("[refresh]\nmissing = \"1s\"", []).unwrap();
+        let error = crate::FrameworkBuilder::default()
+            .bind_config(&config)
+            .await
+            .err()
+            .unwrap();
+        assert!(format!("{:?}", error).contains("/missing"), "{:?}", error);
+    });
+}
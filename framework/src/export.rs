@@ -0,0 +1,373 @@
+//! The formats a page is served in: its HTML, its JSON model, and the rows of its `#[table]`
+//! fields as CSV, NDJSON or an Excel workbook.
+
+use rust_xlsxwriter::{Workbook, XlsxError};
+use serde_json::Value;
+use std::str::FromStr;
+use tide::http::{mime, Mime};
+
+/// A `#[table]` field of a page, as listed in [`View::TABLES`](crate::View::TABLES).
+#[derive(Debug)]
+pub struct Table {
+    pub field: &'static str,
+    pub columns: &'static [Column],
+}
+
+/// A `#[column]` of a [`Table`].
+#[derive(Debug)]
+pub struct Column {
+    pub field: &'static str,
+    /// The `header` of the column, or its `field` if it has none.
+    pub header: &'static str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Format {
+    Html,
+    Json,
+    Csv,
+    Ndjson,
+    Xlsx,
+}
+
+impl Format {
+    /// The format requested by the suffix of `rest`, the remainder of a path after a page's URL.
+    pub(crate) fn from_suffix(rest: &str) -> Option<Self> {
+        match rest {
+            ".json" => Some(Self::Json),
+            ".csv" => Some(Self::Csv),
+            ".ndjson" => Some(Self::Ndjson),
+            ".xlsx" => Some(Self::Xlsx),
+            _ => None,
+        }
+    }
+
+    /// The format preferred by the `Accept` header of `request`, HTML if there is none.
+    ///
+    /// Media ranges are compared by their essence, so `text/html` matches
+    /// `text/html;charset=utf-8`, and `type/*` and `*/*` match with their own q-value. Formats
+    /// that are equally acceptable are picked in the order of [`Format`], HTML first. The table
+    /// exports are only available for pages that have tables.
+    pub(crate) fn negotiate<T>(
+        request: &tide::Request<T>,
+        has_tables: bool,
+    ) -> tide::http::Result<Self> {
+        let accept = match request.header("Accept") {
+            Some(accept) => accept.as_str(),
+            None => return Ok(Self::Html),
+        };
+        let ranges = media_ranges(accept);
+        let mut formats = vec![Self::Html, Self::Json];
+        if has_tables {
+            formats.extend([Self::Csv, Self::Ndjson, Self::Xlsx]);
+        }
+        let mut chosen: Option<(Self, f32)> = None;
+        for format in formats {
+            let q = format.quality(&ranges);
+            if q > 0.0 && chosen.is_none_or(|(_, best)| q > best) {
+                chosen = Some((format, q));
+            }
+        }
+        chosen.map(|(format, _)| format).ok_or_else(|| {
+            tide::http::Error::from_str(tide::StatusCode::NotAcceptable, "Not acceptable")
+        })
+    }
+
+    /// The q-value of the most specific of `ranges` that matches the format, 0 if none does.
+    fn quality(self, ranges: &[(String, f32)]) -> f32 {
+        let mime = self.mime();
+        let (base, sub) = (mime.basetype(), mime.subtype());
+        let specificity = |range: &str| match range.split_once('/') {
+            Some(("*", "*")) => Some(0),
+            Some((b, "*")) if b.eq_ignore_ascii_case(base) => Some(1),
+            Some((b, s)) if b.eq_ignore_ascii_case(base) && s.eq_ignore_ascii_case(sub) => Some(2),
+            _ => None,
+        };
+        ranges
+            .iter()
+            .filter_map(|(range, q)| Some((specificity(range)?, *q)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, q)| q)
+    }
+
+    pub(crate) fn mime(self) -> Mime {
+        match self {
+            Self::Html => mime::HTML,
+            Self::Json => mime::JSON,
+            Self::Csv => Mime::from_str("text/csv").unwrap(),
+            Self::Ndjson => Mime::from_str("application/x-ndjson").unwrap(),
+            Self::Xlsx => {
+                Mime::from_str("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+                    .unwrap()
+            }
+        }
+    }
+}
+
+/// The media ranges of an `Accept` header with their q-values, e.g. `("text/html", 0.8)`.
+///
+/// Ranges with an invalid q-value are ignored.
+fn media_ranges(accept: &str) -> Vec<(String, f32)> {
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parameters = range.split(';').map(str::trim);
+            let essence = parameters.next().filter(|essence| !essence.is_empty())?;
+            let mut q = 1.0;
+            for parameter in parameters {
+                if let Some((name, value)) = parameter.split_once('=') {
+                    if name.trim().eq_ignore_ascii_case("q") {
+                        q = value
+                            .trim()
+                            .parse::<f32>()
+                            .ok()
+                            .filter(|q| (0.0..=1.0).contains(q))?;
+                    }
+                }
+            }
+            Some((essence.to_string(), q))
+        })
+        .collect()
+}
+
+/// Writes `rows` as CSV, with the headers of `table`'s columns as the first record.
+///
+/// Records are separated by CRLF and values quoted when needed, following RFC 4180. Strings
+/// a spreadsheet would read as a formula are prefixed with `'`, see [`is_formula`].
+pub(crate) fn csv(table: &Table, rows: &Value) -> String {
+    let mut out = String::new();
+    let headers = table.columns.iter().map(|column| csv_text(column.header));
+    write_record(&mut out, headers);
+    for row in crate::ssr::rows(rows) {
+        let values = table.columns.iter().map(|column| match &row[column.field] {
+            Value::Null => String::new(),
+            Value::String(value) => csv_text(value),
+            // Numbers and booleans can not be formulas, and `-1` has to stay a number
+            value @ (Value::Number(_) | Value::Bool(_)) => csv_value(&value.to_string()),
+            value => csv_text(&value.to_string()),
+        });
+        write_record(&mut out, values);
+    }
+    out
+}
+
+fn write_record(out: &mut String, values: impl Iterator<Item = String>) {
+    for (i, value) in values.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&value);
+    }
+    out.push_str("\r\n");
+}
+
+/// Whether a spreadsheet opening the export would evaluate `value` as a formula, e.g. a VM
+/// named `=HYPERLINK(...)` or `+cmd|...`.
+fn is_formula(value: &str) -> bool {
+    value.starts_with(['=', '+', '-', '@', '\t', '\r'])
+}
+
+fn csv_text(value: &str) -> String {
+    if is_formula(value) {
+        csv_value(&format!("'{}", value))
+    } else {
+        csv_value(value)
+    }
+}
+
+fn csv_value(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes every row of `rows` as JSON on its own line.
+///
+/// The `_id` and `_enabled` keys the model adds for script.js are left out, so the rows are
+/// exported as the row type serializes them.
+pub(crate) fn ndjson(rows: &Value) -> String {
+    crate::ssr::rows(rows)
+        .iter()
+        .map(|row| {
+            let mut row = row.clone();
+            if let Value::Object(object) = &mut row {
+                object.remove("_id");
+                object.remove("_enabled");
+            }
+            format!("{}\n", row)
+        })
+        .collect()
+}
+
+/// Writes `rows` as an Excel workbook with a single sheet named after `table`, with the
+/// headers of its columns in the first, frozen row.
+///
+/// Numbers and booleans keep their type, so they can be summed and filtered in a spreadsheet.
+/// Everything else is written as a string, never as a formula, so text that starts like one is
+/// shown as it is.
+pub(crate) fn xlsx(table: &Table, rows: &Value) -> Result<Vec<u8>, XlsxError> {
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    // Excel allows at most 31 characters, other names it rejects keep the default `Sheet1`
+    let name: String = table.field.chars().take(31).collect();
+    let _ = sheet.set_name(name);
+    let bold = rust_xlsxwriter::Format::new().set_bold();
+    for (col, column) in table.columns.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, column.header, &bold)?;
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    for (row, values) in crate::ssr::rows(rows).iter().enumerate() {
+        let row = row as u32 + 1;
+        for (col, column) in table.columns.iter().enumerate() {
+            let col = col as u16;
+            match &values[column.field] {
+                Value::Null => continue,
+                Value::Bool(value) => sheet.write_boolean(row, col, *value)?,
+                Value::Number(value) => match value.as_f64() {
+                    Some(value) => sheet.write_number(row, col, value)?,
+                    None => sheet.write_string(row, col, value.to_string())?,
+                },
+                Value::String(value) => sheet.write_string(row, col, value)?,
+                value => sheet.write_string(row, col, value.to_string())?,
+            };
+        }
+    }
+    sheet.autofit();
+    workbook.save_to_buffer()
+}
+
+#[test]
+fn test_csv() {
+    let table = Table {
+        field: "vms",
+        columns: &[
+            Column {
+                field: "name",
+                header: "Name",
+            },
+            Column {
+                field: "cpus",
+                header: "CPUs, total",
+            },
+        ],
+    };
+    let rows = serde_json::json!([
+        { "name": "plain", "cpus": 2 },
+        { "name": "say \"hi\"", "cpus": null },
+        { "name": "two\nlines" },
+    ]);
+    assert_eq!(
+        csv(&table, &rows),
+        "Name,\"CPUs, total\"\r\nplain,2\r\n\"say \"\"hi\"\"\",\r\n\"two\nlines\",\r\n"
+    );
+    assert_eq!(csv(&table, &Value::Null), "Name,\"CPUs, total\"\r\n");
+}
+
+#[test]
+fn test_csv_formulas() {
+    let table = Table {
+        field: "vms",
+        columns: &[
+            Column {
+                field: "name",
+                header: "=Name",
+            },
+            Column {
+                field: "cpus",
+                header: "CPUs",
+            },
+        ],
+    };
+    let rows = serde_json::json!([
+        { "name": "=HYPERLINK(\"http://evil\")", "cpus": -1 },
+        { "name": "+cmd|' /C calc'!A0", "cpus": 1 },
+        { "name": "-2", "cpus": 1.5 },
+        { "name": "@SUM(A1)", "cpus": true },
+        { "name": "\tindented", "cpus": null },
+        { "name": "\rreturn", "cpus": null },
+        { "name": "a=b", "cpus": null },
+    ]);
+    assert_eq!(
+        csv(&table, &rows),
+        "'=Name,CPUs\r\n\
+         \"'=HYPERLINK(\"\"http://evil\"\")\",-1\r\n\
+         '+cmd|' /C calc'!A0,1\r\n\
+         '-2,1.5\r\n\
+         '@SUM(A1),true\r\n\
+         '\tindented,\r\n\
+         \"'\rreturn\",\r\n\
+         a=b,\r\n"
+    );
+}
+
+#[test]
+fn test_ndjson() {
+    let rows = serde_json::json!([
+        { "name": "a", "_id": "1", "_enabled": { "start": true } },
+        { "name": "b\nc", "_id": "2" },
+    ]);
+    assert_eq!(ndjson(&rows), "{\"name\":\"a\"}\n{\"name\":\"b\\nc\"}\n");
+    assert_eq!(ndjson(&Value::Null), "");
+}
+
+#[test]
+fn test_xlsx() {
+    let table = Table {
+        field: "vms",
+        columns: &[Column {
+            field: "name",
+            header: "Name",
+        }],
+    };
+    let rows = serde_json::json!([{ "name": "a" }, { "name": 5 }, { "name": null }]);
+    let workbook = xlsx(&table, &rows).unwrap();
+    // Workbooks are zip archives
+    assert!(workbook.starts_with(b"PK"));
+}
+
+#[test]
+fn test_xlsx_formulas() {
+    use std::io::Read;
+
+    let table = Table {
+        field: "vms",
+        columns: &[Column {
+            field: "name",
+            header: "=Name",
+        }],
+    };
+    let rows =
+        serde_json::json!([{ "name": "=HYPERLINK(\"http://evil\")" }, { "name": "+cmd|A0" }]);
+    let workbook = xlsx(&table, &rows).unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(workbook)).unwrap();
+    let mut read = |name: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    let sheet = read("xl/worksheets/sheet1.xml");
+    assert!(!sheet.contains("<f>"), "{}", sheet);
+    let strings = read("xl/sharedStrings.xml");
+    assert!(
+        strings.contains("<t>=HYPERLINK(\"http://evil\")</t>"),
+        "{}",
+        strings
+    );
+    assert!(strings.contains("<t>+cmd|A0</t>"), "{}", strings);
+}
+
+#[test]
+fn test_xlsx_long_table_name() {
+    let table = Table {
+        field: "virtual_machines_of_every_hypervisor",
+        columns: &[],
+    };
+    let workbook = xlsx(&table, &serde_json::json!([])).unwrap();
+    assert!(workbook.starts_with(b"PK"));
+}
@@ -0,0 +1,275 @@
+//! Background jobs, for actions that take longer than a request should.
+//!
+//! An action starts a job with [`Request::spawn_job`](crate::Request::spawn_job) and returns its
+//! [`Job`] handle. The page then polls `/jobs/<id>.json` and shows a progress bar until the job
+//! completes.
+
+use crate::server::Notify;
+use crate::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long finished jobs are kept, so clients can pick up their result.
+const KEEP_FINISHED: Duration = Duration::from_secs(60 * 60);
+
+/// A job running in the background, returned by an action instead of `()`.
+#[derive(Clone, Debug)]
+pub struct Job {
+    id: u64,
+}
+
+impl Job {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The URL at which the status of this job is served.
+    pub fn url(&self) -> String {
+        format!("/jobs/{}.json", self.id)
+    }
+}
+
+/// Passed to a running job to report its progress.
+#[derive(Clone)]
+pub struct JobContext {
+    job: Job,
+    status: Arc<Mutex<JobStatus>>,
+}
+
+impl JobContext {
+    /// The handle of the job, e.g. for the URL of its status.
+    pub(crate) fn job(&self) -> Job {
+        self.job.clone()
+    }
+
+    /// Sets the progress of the job, from `0.0` to `1.0`. Until this is called, the progress is
+    /// shown as indeterminate.
+    pub fn progress(&self, progress: f64) {
+        lock(&self.status).progress = Some(progress.clamp(0.0, 1.0));
+    }
+
+    /// Adds a line to the log of the job.
+    pub fn log(&self, line: impl Into<String>) {
+        lock(&self.status).log.push(line.into());
+    }
+}
+
+enum JobState {
+    Running,
+    Succeeded,
+    Failed(String),
+}
+
+struct JobStatus {
+    name: String,
+    state: JobState,
+    progress: Option<f64>,
+    log: Vec<String>,
+    finished: Option<Instant>,
+}
+
+impl JobStatus {
+    fn finish(&mut self, result: Result) {
+        self.state = match result {
+            Ok(()) => {
+                self.progress = Some(1.0);
+                JobState::Succeeded
+            }
+            Err(e) => JobState::Failed(format!("{:?}", e)),
+        };
+        self.finished = Some(Instant::now());
+    }
+
+    fn to_json(&self, id: u64) -> Value {
+        let (state, error) = match &self.state {
+            JobState::Running => ("running", None),
+            JobState::Succeeded => ("succeeded", None),
+            JobState::Failed(error) => ("failed", Some(error)),
+        };
+        serde_json::json!({
+            "id": id,
+            "name": self.name,
+            "state": state,
+            "progress": self.progress,
+            "log": self.log,
+            "error": error,
+        })
+    }
+}
+
+/// Marks a job as failed if its task ends without finishing it, e.g. because it panicked.
+struct FinishGuard(Arc<Mutex<JobStatus>>, Notify);
+
+impl Drop for FinishGuard {
+    fn drop(&mut self) {
+        {
+            let mut status = lock(&self.0);
+            if status.finished.is_none() {
+                status.finish(Err("Job panicked".to_string().into()));
+            }
+        }
+        self.1.notify();
+    }
+}
+
+/// The jobs of a server, shared with every request through its extensions.
+#[derive(Clone, Default)]
+pub(crate) struct Jobs {
+    jobs: Arc<Mutex<HashMap<u64, Arc<Mutex<JobStatus>>>>>,
+    finished: Notify,
+}
+
+impl Jobs {
+    pub(crate) fn spawn<F, Fut>(&self, name: String, job: F) -> Job
+    where
+        F: FnOnce(JobContext) -> Fut,
+        Fut: Future<Output = Result> + Send + 'static,
+    {
+        let status = Arc::new(Mutex::new(JobStatus {
+            name,
+            state: JobState::Running,
+            progress: None,
+            log: Vec::new(),
+            finished: None,
+        }));
+        let id = {
+            let mut jobs = lock(&self.jobs);
+            jobs.retain(|_, status| {
+                lock(status)
+                    .finished
+                    .is_none_or(|finished| finished.elapsed() < KEEP_FINISHED)
+            });
+            let id = std::iter::repeat_with(random_id)
+                .find(|id| !jobs.contains_key(id))
+                .expect("Ids are unbounded");
+            jobs.insert(id, status.clone());
+            id
+        };
+
+        let future = job(JobContext {
+            job: Job { id },
+            status: status.clone(),
+        });
+        let finished = self.finished.clone();
+        async_std::task::spawn(async move {
+            let guard = FinishGuard(status, finished);
+            let result = future.await;
+            lock(&guard.0).finish(result);
+        });
+        Job { id }
+    }
+
+    /// The number of jobs that have not finished yet.
+    pub(crate) fn running(&self) -> usize {
+        lock(&self.jobs)
+            .values()
+            .filter(|status| lock(status).finished.is_none())
+            .count()
+    }
+
+    /// Completes when a job finishes.
+    pub(crate) async fn finished(&self) {
+        self.finished.notified().await
+    }
+
+    /// The status of job `id` as served at `/jobs/<id>.json`.
+    pub(crate) fn status(&self, id: u64) -> Option<Value> {
+        let status = lock(&self.jobs).get(&id)?.clone();
+        let json = lock(&status).to_json(id);
+        Some(json)
+    }
+}
+
+/// A new job id, random so that clients can not read the status and logs of other users' jobs
+/// by counting up.
+fn random_id() -> u64 {
+    let mut bytes = [0; 8];
+    getrandom::getrandom(&mut bytes).expect("The OS has no random number generator");
+    // 53 bits, which JavaScript reads from the status JSON without rounding
+    u64::from_be_bytes(bytes) >> 11
+}
+
+/// A job that panicked while holding a lock should not take the status page down with it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+async fn wait_until_finished(jobs: &Jobs, job: &Job) -> Value {
+    loop {
+        let status = jobs.status(job.id()).unwrap();
+        if status["state"] != "running" {
+            return status;
+        }
+        async_std::task::sleep(Duration::from_millis(1)).await;
+    }
+}
+
+#[test]
+fn test_job_reports_progress() {
+    async_std::task::block_on(async {
+        let jobs = Jobs::default();
+        let (sender, receiver) = async_std::channel::bounded::<()>(1);
+        let job = jobs.spawn("Start VMs".to_string(), |context| async move {
+            context.log("Starting infra");
+            context.progress(0.5);
+            receiver.recv().await.unwrap();
+            Ok(())
+        });
+        assert_eq!(job.url(), format!("/jobs/{}.json", job.id()));
+
+        while jobs.status(job.id()).unwrap()["progress"] != 0.5 {
+            async_std::task::sleep(Duration::from_millis(1)).await;
+        }
+        let status = jobs.status(job.id()).unwrap();
+        assert_eq!(status["name"], "Start VMs");
+        assert_eq!(status["state"], "running");
+        assert_eq!(status["log"], serde_json::json!(["Starting infra"]));
+        assert_eq!(jobs.running(), 1);
+
+        sender.send(()).await.unwrap();
+        let status = wait_until_finished(&jobs, &job).await;
+        assert_eq!(status["state"], "succeeded");
+        assert_eq!(status["progress"], 1.0);
+        assert_eq!(status["error"], Value::Null);
+        assert_eq!(jobs.running(), 0);
+    });
+}
+
+#[test]
+fn test_job_failures() {
+    async_std::task::block_on(async {
+        let jobs = Jobs::default();
+        let failed = jobs.spawn("fails".to_string(), |_| async {
+            Err("virsh not found".to_string().into())
+        });
+        let status = wait_until_finished(&jobs, &failed).await;
+        assert_eq!(status["state"], "failed");
+        assert!(status["error"]
+            .as_str()
+            .unwrap()
+            .contains("virsh not found"));
+
+        let panicked = jobs.spawn("panics".to_string(), |_| async { panic!("oops") });
+        let status = wait_until_finished(&jobs, &panicked).await;
+        assert_eq!(status["state"], "failed");
+
+        assert_ne!(failed.id(), panicked.id());
+        assert!(jobs.status(failed.id() ^ 1).is_none());
+        // Not counted up, so ids do not reveal other jobs
+        assert!(failed.id().abs_diff(panicked.id()) > 1000);
+        assert!(failed.id() < 1 << 53);
+    });
+}
+
+#[test]
+fn test_spawn_job_without_framework() {
+    let request = tide::http::Request::new(tide::http::Method::Post, "http://localhost/vms");
+    let request: tide::Request<()> = request.into();
+    let request = crate::Request::new(request);
+    let error = request.spawn_job("Start VMs", |_| async { Ok(()) });
+    assert!(matches!(error, Err(crate::Error::String(_))));
+}
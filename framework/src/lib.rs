@@ -1,26 +1,74 @@
+// Lets `#[derive(Page)]` be used on the built-in pages
+extern crate self as framework;
+
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
 use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tide::http::mime;
+use tracing::Instrument;
 
 pub use derive::Page;
+pub use schemars;
+#[doc(hidden)]
+pub use serde_json;
+pub use shared::{duration, escape};
+
+pub mod audit;
+mod cache;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod config;
+pub mod export;
+pub mod jobs;
+mod listener;
+mod metrics;
+pub mod openapi;
+pub mod security;
+pub mod server;
+#[doc(hidden)]
+pub mod ssr;
+pub mod testing;
+
+pub use audit::{AuditSink, JsonLinesSink, MemorySink};
+pub use config::{Assets, Config};
+pub use jobs::{Job, JobContext};
+pub use security::SecurityHeaders;
+pub use server::{Server, ShutdownHandle};
 
 pub mod prelude {
-    pub use super::{Constructor, Error, FrameworkBuilder, Page, Request, Result, TableRow};
+    pub use super::{
+        Component, Constructor, Error, FrameworkBuilder, Job, JobContext, Page, Request, Result,
+        TableRow, View,
+    };
     pub use async_trait::async_trait;
+    pub use schemars::JsonSchema;
 }
 
 pub struct FrameworkBuilder<T> {
     state: T,
     pages: Vec<Box<dyn PageHandlerTrait<T>>>,
+    refresh: HashMap<String, Option<Duration>>,
+    security_headers: SecurityHeaders,
+    // `None` keeps the most recent entries in memory
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    audit_page: bool,
+    user_header: Option<String>,
+    users: HashMap<String, String>,
+    assets: Assets,
+    shutdown_timeout: Duration,
+    redirect_http: Option<String>,
 }
 
 impl Default for FrameworkBuilder<()> {
     fn default() -> Self {
-        Self {
-            state: (),
-            pages: Vec::new(),
-        }
+        Self::with_state(())
     }
 }
 
@@ -29,6 +77,15 @@ impl<T: Send + Sync + Clone + 'static> FrameworkBuilder<T> {
         Self {
             state,
             pages: Vec::new(),
+            refresh: HashMap::new(),
+            security_headers: SecurityHeaders::default(),
+            audit_sink: None,
+            audit_page: false,
+            user_header: None,
+            users: HashMap::new(),
+            assets: Assets::default(),
+            shutdown_timeout: Duration::from_secs(30),
+            redirect_http: None,
         }
     }
 
@@ -37,16 +94,308 @@ impl<T: Send + Sync + Clone + 'static> FrameworkBuilder<T> {
         self
     }
 
-    pub async fn run(self, listener: impl tide::listener::ToListener<T>) -> Result {
+    /// Overrides the `#[page(refresh = "...")]` interval of page `P`. `None` disables refreshing.
+    ///
+    /// Individual requests can still override this with a `?refresh=10s` or `?refresh=off` query.
+    pub fn refresh<P: Page<T>>(mut self, refresh: Option<Duration>) -> Self {
+        self.refresh.insert(P::URL.to_string(), refresh);
+        self
+    }
+
+    /// Configures the Content-Security-Policy and other security headers sent with every
+    /// response. By default a strict policy is sent that only allows the framework's own scripts.
+    pub fn security_headers(mut self, security_headers: SecurityHeaders) -> Self {
+        self.security_headers = security_headers;
+        self
+    }
+
+    /// Sets where the audit log of every invoked action is stored, e.g. a [`JsonLinesSink`]. By
+    /// default only the most recent entries are kept, in memory.
+    pub fn audit_sink(mut self, audit_sink: impl AuditSink + 'static) -> Self {
+        self.audit_sink = Some(Arc::new(audit_sink));
+        self
+    }
+
+    /// Whether the most recent entries of the audit log are shown at `/audit`, off by default.
+    ///
+    /// They show who invoked which actions on which rows, so only enable this together with
+    /// [`basic_auth`](Self::basic_auth) or behind an authenticating reverse proxy.
+    pub fn audit_page(mut self, audit_page: bool) -> Self {
+        self.audit_page = audit_page;
+        self
+    }
+
+    /// Takes the user of a request from `header`, e.g. `X-Forwarded-User`, for the audit log.
+    ///
+    /// Only use this behind a reverse proxy that authenticates users and always sets this header,
+    /// as clients could otherwise send any user they like.
+    pub fn user_header(mut self, header: impl Into<String>) -> Self {
+        self.user_header = Some(header.into());
+        self
+    }
+
+    /// Requires every request to log in with HTTP basic auth, as one of `users` given as
+    /// `(name, password)` pairs. The name is then the [`Request::user`].
+    ///
+    /// Browsers send the password with every request, so only use this with TLS.
+    pub fn basic_auth(mut self, users: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.users = users.into_iter().collect();
+        self
+    }
+
+    /// Sets the script and stylesheets every page loads, e.g. to add your own stylesheet.
+    pub fn assets(mut self, assets: Assets) -> Self {
+        self.assets = assets;
+        self
+    }
+
+    /// Applies the users, assets, refresh intervals, audit log and HTTP redirect of `config`.
+    ///
+    /// Settings that `config` does not set are kept, and so is an
+    /// [`audit_sink`](Self::audit_sink) set before instead of the configured path.
+    pub fn config(mut self, config: &Config) -> Self {
+        self.refresh.extend(config.refresh.clone());
+        if !config.users.is_empty() {
+            self = self.basic_auth(config.users.clone());
+        }
+        if let Some(script) = &config.assets.script {
+            self.assets.script = script.clone();
+        }
+        if let Some(stylesheets) = &config.assets.stylesheets {
+            self.assets.stylesheets = stylesheets.clone();
+        }
+        if let (None, Some(path)) = (&self.audit_sink, &config.audit.path) {
+            self = self.audit_sink(JsonLinesSink::new(path));
+        }
+        if let Some(audit_page) = config.audit.page {
+            self.audit_page = audit_page;
+        }
+        if let Some(redirect_http) = config
+            .tls
+            .as_ref()
+            .and_then(|tls| tls.redirect_http.clone())
+        {
+            self.redirect_http = Some(redirect_http);
+        }
+        self
+    }
+
+    /// Like [`run`](Self::run), but applies `config` and binds to its addresses, with TLS if it
+    /// is configured.
+    pub async fn run_config(self, config: &Config) -> Result {
+        self.bind_config(config)
+            .await?
+            .shutdown_on_signal()
+            .run()
+            .await
+    }
+
+    /// Like [`bind`](Self::bind), see [`run_config`](Self::run_config).
+    pub async fn bind_config(self, config: &Config) -> Result<Server<T>> {
+        if config.bind.is_empty() {
+            return Err("No address to bind to in the configuration"
+                .to_string()
+                .into());
+        }
+        let addresses = config
+            .bind
+            .iter()
+            .map(|address| resolve(address.as_str()))
+            .collect::<Result<Vec<_>>>()?;
+        let tls = config.tls.as_ref().map(|tls| listener::TlsFiles {
+            cert: tls.cert.clone(),
+            key: tls.key.clone(),
+        });
+        self.config(config).bind_listeners(addresses, tls).await
+    }
+
+    /// How long a shutdown waits for active requests and running jobs to finish. Defaults to
+    /// 30 seconds.
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Also listens for plain HTTP on `address` when serving TLS, e.g. `"0.0.0.0:80"`, and
+    /// redirects every request there to HTTPS. Ignored by [`bind`](Self::bind).
+    pub fn redirect_http(mut self, address: impl Into<String>) -> Self {
+        self.redirect_http = Some(address.into());
+        self
+    }
+
+    /// Binds to `address` and serves requests until SIGINT or SIGTERM, then shuts down
+    /// gracefully.
+    pub async fn run(self, address: impl std::net::ToSocketAddrs) -> Result {
+        self.bind(address).await?.shutdown_on_signal().run().await
+    }
+
+    /// Like [`run`](Self::run), but serves HTTPS with the certificate chain and private key from
+    /// the PEM files `cert` and `key`.
+    pub async fn run_tls(
+        self,
+        address: impl std::net::ToSocketAddrs,
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Result {
+        self.bind_tls(address, cert, key)
+            .await?
+            .shutdown_on_signal()
+            .run()
+            .await
+    }
+
+    /// Binds to `address` without accepting connections yet, see [`Server::run`].
+    ///
+    /// Pass e.g. `"127.0.0.1:0"` to let the OS pick a free port, and get it from
+    /// [`Server::addresses`]. Use [`bind_config`](Self::bind_config) to listen on several
+    /// addresses.
+    pub async fn bind(self, address: impl std::net::ToSocketAddrs) -> Result<Server<T>> {
+        self.bind_listeners(vec![resolve(address)?], None).await
+    }
+
+    /// Like [`bind`](Self::bind), but for HTTPS, see [`run_tls`](Self::run_tls).
+    ///
+    /// Only TLS servers send the
+    /// [`strict_transport_security`](SecurityHeaders::strict_transport_security) header, and
+    /// listen on the [`redirect_http`](Self::redirect_http) address.
+    pub async fn bind_tls(
+        self,
+        address: impl std::net::ToSocketAddrs,
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Result<Server<T>> {
+        let tls = listener::TlsFiles {
+            cert: cert.as_ref().to_path_buf(),
+            key: key.as_ref().to_path_buf(),
+        };
+        self.bind_listeners(vec![resolve(address)?], Some(tls))
+            .await
+    }
+
+    /// Binds a listener to each of `addresses`, which serves HTTPS if `tls` is set.
+    async fn bind_listeners(
+        self,
+        addresses: Vec<Vec<SocketAddr>>,
+        tls: Option<listener::TlsFiles>,
+    ) -> Result<Server<T>> {
+        let redirect_http = self.redirect_http.clone();
+        let shutdown_timeout = self.shutdown_timeout;
+        let (server, jobs, metrics) = self.build(tls.is_some())?;
+        let shutdown = ShutdownHandle::default();
+        let connections = listener::Connections::default();
+        let listeners: Vec<_> = addresses
+            .into_iter()
+            .map(|addresses| {
+                listener::HttpListener::new(
+                    addresses,
+                    tls.clone(),
+                    shutdown.clone(),
+                    connections.clone(),
+                )
+            })
+            .collect();
+        let listener = server.bind(listeners).await.map_err(Error::Tide)?;
+
+        let redirect = match redirect_http {
+            Some(address) if tls.is_some() => {
+                let port = tide::listener::Listener::info(&listener)
+                    .iter()
+                    .find_map(|info| info.connection().parse::<tide::http::Url>().ok()?.port())
+                    .unwrap_or(443);
+                let mut redirect = tide::new();
+                redirect.with(server::RedirectToHttps { port });
+                let listener = listener::HttpListener::new(
+                    resolve(address.as_str())?,
+                    None,
+                    shutdown.clone(),
+                    connections.clone(),
+                );
+                let listener = redirect.bind(listener).await.map_err(Error::Tide)?;
+                Some(Box::new(listener) as Box<dyn tide::listener::Listener<()>>)
+            }
+            _ => None,
+        };
+        Ok(Server {
+            listener: Box::new(listener),
+            redirect,
+            shutdown,
+            shutdown_on_signal: false,
+            shutdown_timeout,
+            jobs,
+            metrics,
+            connections,
+        })
+    }
+
+    /// The tide server with the framework's routes, and the jobs and metrics shared by its
+    /// requests.
+    pub(crate) fn build(
+        mut self,
+        tls: bool,
+    ) -> Result<(tide::Server<T>, jobs::Jobs, metrics::Metrics)> {
+        if self.audit_page {
+            self.pages
+                .insert(0, PageHandler::<T, audit::AuditPage>::boxed());
+        }
+        for (i, page) in self.pages.iter().enumerate() {
+            if self.pages[..i]
+                .iter()
+                .any(|other| other.url() == page.url())
+            {
+                return Err(format!("More than one page is served at {}", page.url()).into());
+            }
+        }
+        // Answered before the pages are routed
+        for url in ["/metrics", "/openapi"] {
+            if self.pages.iter().any(|page| page.url() == url) {
+                return Err(format!("{} is served by the framework, not by a page", url).into());
+            }
+        }
+        if let Some(url) = self
+            .refresh
+            .keys()
+            .find(|url| !self.pages.iter().any(|page| page.url() == url.as_str()))
+        {
+            return Err(format!("Refresh interval configured for unknown page {}", url).into());
+        }
+        if !tls {
+            // Browsers ignore it over plain HTTP, and it would pin a reverse proxy's host to HTTPS
+            self.security_headers.strict_transport_security = None;
+        }
+        let audit_sink = self
+            .audit_sink
+            .unwrap_or_else(|| Arc::new(MemorySink::bounded(audit::PAGE_ENTRIES)));
+        let jobs = jobs::Jobs::default();
+        let metrics = metrics::Metrics::default();
         let mut server = tide::with_state(self.state);
-        server.with(FrameworkMiddleware { pages: self.pages });
+        let openapi = openapi::document(&self.pages).to_string();
+        server.with(FrameworkMiddleware {
+            openapi,
+            pages: self.pages,
+            refresh: Arc::new(self.refresh),
+            security_headers: self.security_headers,
+            jobs: jobs.clone(),
+            metrics: metrics.clone(),
+            audit: audit::Audit(audit_sink),
+            user_header: self.user_header,
+            users: self.users,
+            assets: self.assets,
+        });
         server
             .at("/static/script.js")
             .get(|_| serve_static_file(mime::JAVASCRIPT, include_str!("../static/script.js")));
-        server.listen(listener).await.map_err(Error::Tide)
+        server
+            .at("/static/style.css")
+            .get(|_| serve_static_file(mime::CSS, include_str!("../static/style.css")));
+        Ok((server, jobs, metrics))
     }
 }
 
+/// The addresses `address` resolves to, e.g. both `127.0.0.1` and `::1` for `localhost`.
+fn resolve(address: impl std::net::ToSocketAddrs) -> Result<Vec<SocketAddr>> {
+    Ok(address.to_socket_addrs()?.collect())
+}
+
 async fn serve_static_file(mime: mime::Mime, str: &'static str) -> tide::Result<tide::Response> {
     Ok(tide::Response::builder(200)
         .content_type(mime)
@@ -55,22 +404,63 @@ async fn serve_static_file(mime: mime::Mime, str: &'static str) -> tide::Result<
 }
 
 struct FrameworkMiddleware<T> {
+    /// The OpenAPI document of `pages`, served at `/openapi.json`
+    openapi: String,
     pages: Vec<Box<dyn PageHandlerTrait<T>>>,
+    refresh: Arc<HashMap<String, Option<Duration>>>,
+    security_headers: SecurityHeaders,
+    jobs: jobs::Jobs,
+    metrics: metrics::Metrics,
+    audit: audit::Audit,
+    user_header: Option<String>,
+    users: HashMap<String, String>,
+    assets: Assets,
 }
 
 impl<T> FrameworkMiddleware<T> {
+    /// The context pages are rendered in for `request`, with the override of its `?refresh=`
+    /// query.
+    fn context(&self, request: &tide::Request<T>) -> std::result::Result<RenderContext, String> {
+        let refresh = request
+            .url()
+            .query_pairs()
+            .find(|(key, _)| key == "refresh")
+            .map(|(_, value)| value);
+        let refresh = match refresh.as_deref() {
+            Some("off") => Some(None),
+            Some(refresh) => Some(Some(duration::parse_refresh(refresh)?)),
+            None => None,
+        };
+        // Only the parameters the framework reads are left out, the page could depend on others
+        let mut url = request.url().clone();
+        url.query_pairs_mut().clear().extend_pairs(
+            request
+                .url()
+                .query_pairs()
+                .filter(|(key, _)| key != "refresh" && key != "table"),
+        );
+        Ok(RenderContext {
+            refresh,
+            configured: self.refresh.clone(),
+            query: url.query().unwrap_or_default().to_string(),
+        })
+    }
+
     fn generate_html(&self, status: u16, body: impl AsRef<str>) -> tide::Result {
-        const HEADER: &str = r#"<!DOCTYPE html>
-<html>
-    <head>
-        <script type='text/javascript' src='https://cdnjs.cloudflare.com/ajax/libs/knockout/3.5.0/knockout-min.js'></script>
-        <script type='text/javascript' src='/static/script.js'></script>
-    </head>
-    <body>
-"#;
+        let mut header = String::from("<!DOCTYPE html>\n<html>\n    <head>\n");
+        for stylesheet in &self.assets.stylesheets {
+            header += &format!(
+                "        <link rel='stylesheet' href='{}'>\n",
+                escape::escape_html(stylesheet)
+            );
+        }
+        header += &format!(
+            "        <script type='text/javascript' src='{}'></script>\n    </head>\n    <body>\n",
+            escape::escape_html(&self.assets.script)
+        );
         const FOOTER: &str = r#"    </body>
         </html>"#;
-        let body = format!("{}{}{}", HEADER, body.as_ref(), FOOTER);
+        let body = format!("{}{}{}", header, body.as_ref(), FOOTER);
         Ok(tide::Response::builder(status)
             .content_type(mime::HTML)
             .body(body)
@@ -91,56 +481,407 @@ where
     T: Clone + Send + Sync + 'static,
 {
     async fn handle(&self, request: tide::Request<T>, next: tide::Next<'_, T>) -> tide::Result {
+        let start = Instant::now();
+        let _active = self.metrics.start_request();
+        let page = self.page_label(request.url().path());
+        let span = tracing::info_span!(
+            "request",
+            method = %request.method(),
+            path = %request.url().path(),
+            page,
+            status = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+            construct_ms = tracing::field::Empty,
+        );
+
+        let poll = self.poll(page, &request);
+        let mut response = self.route(request, next).instrument(span.clone()).await?;
+        self.security_headers.apply(&mut response);
+
+        let status = response.status() as u16;
+        if let Some((client, refresh)) = poll.filter(|_| response.status().is_success()) {
+            self.metrics.observe_poll(page, &client, refresh);
+        }
+        let latency = start.elapsed();
+        self.metrics.observe_request(page, status, latency);
+        span.record("status", status);
+        span.record("latency_ms", latency.as_secs_f64() * 1000.0);
+        span.in_scope(|| {
+            if status >= 500 {
+                tracing::error!("request failed");
+            } else {
+                tracing::info!("request handled");
+            }
+        });
+        Ok(response)
+    }
+}
+
+impl<T> FrameworkMiddleware<T> {
+    /// The name of the user that logged in with the basic auth credentials of `request`.
+    fn authenticate(&self, request: &tide::Request<T>) -> Option<String> {
+        let auth = tide::http::auth::BasicAuth::from_headers(request).ok()??;
+        let password = self.users.get(auth.username())?;
+        // Compares in constant time, so the time of a response does not give away the password
+        let difference = password
+            .bytes()
+            .zip(auth.password().bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b));
+        (difference == 0 && password.len() == auth.password().len())
+            .then(|| auth.username().to_string())
+    }
+
+    /// The client id and refresh interval of `request`, if it is script.js refreshing the model of
+    /// `page`.
+    fn poll(&self, page: &str, request: &tide::Request<T>) -> Option<(String, Duration)> {
+        if request.method() != tide::http::Method::Get
+            || !page.starts_with('/')
+            || !request.url().path().ends_with(".json")
+        {
+            return None;
+        }
+        let client = request.header(metrics::CLIENT_HEADER)?.as_str();
+        let refresh = request
+            .header(metrics::REFRESH_HEADER)?
+            .as_str()
+            .parse()
+            .ok()?;
+        // Bounds what clients can make the metrics keep
+        let refresh = Duration::from_millis(refresh).min(Duration::from_secs(60 * 60));
+        (client.len() <= 64).then(|| (client.to_string(), refresh))
+    }
+
+    /// The page `path` belongs to, used to label its metrics. Other paths are grouped, so
+    /// requests for random URLs can not create new metrics.
+    fn page_label(&self, path: &str) -> &str {
+        if path.starts_with("/static/") {
+            return "static";
+        } else if path.starts_with("/jobs/") {
+            return "jobs";
+        } else if path == "/metrics" {
+            return "metrics";
+        } else if path == "/openapi.json" {
+            return "openapi";
+        }
+        for page in &self.pages {
+            let url = page.url();
+            let rest = match path.strip_prefix(url) {
+                Some(rest) => rest,
+                None => continue,
+            };
+            let export = export::Format::from_suffix(rest).is_some();
+            if rest.is_empty() || export || rest.starts_with("/actions/") {
+                return url;
+            }
+        }
+        "unknown"
+    }
+}
+
+impl<T> FrameworkMiddleware<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    async fn route(&self, mut request: tide::Request<T>, next: tide::Next<'_, T>) -> tide::Result {
+        if !self.users.is_empty() {
+            match self.authenticate(&request) {
+                Some(user) => request.set_ext(audit::User(user)),
+                None => {
+                    return Ok(tide::Response::builder(401)
+                        .header(
+                            "WWW-Authenticate",
+                            "Basic realm=\"framework\", charset=\"UTF-8\"",
+                        )
+                        .build())
+                }
+            };
+        }
         if request.url().path().starts_with("/static") {
             return Ok(next.run(request).await);
         }
+        // Makes `Request::spawn_job` available to constructors and actions
+        request.set_ext(self.jobs.clone());
+        request.set_ext(self.audit.clone());
+        if let Some(header) = &self.user_header {
+            // Users that logged in with basic auth can not pretend to be someone else
+            let user = match request.ext::<audit::User>() {
+                Some(_) => None,
+                None => request.header(header.as_str()),
+            };
+            if let Some(user) = user {
+                let user = audit::User(user.as_str().to_string());
+                request.set_ext(user);
+            }
+        }
+        request.set_ext(self.metrics.clone());
         let path = request.url().path();
-        if let Some(stripped) = path.strip_suffix(".json") {
+        if path == "/metrics" {
+            return Ok(tide::Response::builder(200)
+                .content_type("text/plain; version=0.0.4")
+                .body(self.metrics.render())
+                .build());
+        }
+        if path == "/openapi.json" {
+            return Ok(tide::Response::builder(200)
+                .content_type(mime::JSON)
+                .body(self.openapi.as_str())
+                .build());
+        }
+        let job = path
+            .strip_prefix("/jobs/")
+            .and_then(|id| id.strip_suffix(".json"))
+            .and_then(|id| id.parse().ok());
+        if let Some(id) = job {
+            match self.jobs.status(id) {
+                Some(status) => self.generate_json(200, status),
+                None => self.generate_json(404, serde_json::json!({ "error": "not found" })),
+            }
+        } else if request.method() == tide::http::Method::Post {
             for page in &self.pages {
-                if stripped == page.url() {
-                    let (state, body) = match page.generate_json(Request::new(request)).await {
-                        Ok(response) => (200, response),
-                        Err(e) => (500, serde_json::json!({ "error": format!("{:?}", e) })),
-                    };
-                    return self.generate_json(state, body);
+                let function = path
+                    .strip_prefix(page.url())
+                    .and_then(|rest| rest.strip_prefix("/actions/"));
+                if let Some(function) = function {
+                    let function = function.to_string();
+                    return self.invoke(page.as_ref(), request, &function).await;
                 }
             }
             self.generate_json(404, serde_json::json!({ "error": "not found" }))
         } else {
             for page in &self.pages {
-                if page.url() == path {
-                    let (state, body) = match page.generate(Request::new(request)).await {
-                        Ok(response) => (200, response),
-                        Err(e) => (500, format!("<h1>Internal server error</h1>{:?}", e)),
-                    };
-                    return self.generate_html(state, body);
-                }
+                let format = match path.strip_prefix(page.url()) {
+                    Some("") => None,
+                    Some(rest) => match export::Format::from_suffix(rest) {
+                        Some(format) => Some(format),
+                        None => continue,
+                    },
+                    None => continue,
+                };
+                return self.generate(page.as_ref(), request, format).await;
+            }
+            if path.ends_with(".json") {
+                return self.generate_json(404, serde_json::json!({ "error": "not found" }));
             }
             self.generate_html(404, "Not found")
         }
     }
 }
 
+impl<T> FrameworkMiddleware<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    /// Serves `page` in `format`, or in the format negotiated with the `Accept` header when it
+    /// is requested at its plain URL.
+    async fn generate(
+        &self,
+        page: &dyn PageHandlerTrait<T>,
+        request: tide::Request<T>,
+        format: Option<export::Format>,
+    ) -> tide::Result {
+        let negotiated = format.is_none();
+        let format = match format {
+            Some(format) => format,
+            None => match export::Format::negotiate(&request, !page.tables().is_empty()) {
+                Ok(format) => format,
+                Err(e) => return self.generate_html(e.status().into(), "Not acceptable"),
+            },
+        };
+        let mut response = match format {
+            export::Format::Html => self.generate_page(page, request).await?,
+            export::Format::Json => self.generate_model(page, request).await?,
+            export::Format::Csv | export::Format::Ndjson | export::Format::Xlsx => {
+                self.generate_export(page, request, format).await?
+            }
+        };
+        if negotiated {
+            response.append_header("Vary", "Accept");
+        }
+        Ok(response)
+    }
+
+    async fn generate_page(
+        &self,
+        page: &dyn PageHandlerTrait<T>,
+        request: tide::Request<T>,
+    ) -> tide::Result {
+        let context = match self.context(&request) {
+            Ok(context) => context,
+            Err(e) => return self.generate_html(400, escape::escape_html(&e)),
+        };
+        let (state, body) = match page.generate(Request::new(request), context).await {
+            Ok(response) => (200, response),
+            Err(e) => {
+                tracing::error!(error = ?e, "Could not generate the page");
+                (
+                    500,
+                    format!(
+                        "<h1>Internal server error</h1>{}",
+                        escape::escape_html(&format!("{:?}", e))
+                    ),
+                )
+            }
+        };
+        self.generate_html(state, body)
+    }
+
+    async fn generate_model(
+        &self,
+        page: &dyn PageHandlerTrait<T>,
+        request: tide::Request<T>,
+    ) -> tide::Result {
+        let (state, body) = match page.generate_json(Request::new(request)).await {
+            Ok(response) => (200, response),
+            Err(e) => {
+                tracing::error!(error = ?e, "Could not generate the model");
+                (500, serde_json::json!({ "error": format!("{:?}", e) }))
+            }
+        };
+        self.generate_json(state, body)
+    }
+
+    /// Serves the rows of a `#[table]` of `page` as CSV, NDJSON or xlsx: the table named by the
+    /// `?table=<field>` query parameter, or the first one.
+    async fn generate_export(
+        &self,
+        page: &dyn PageHandlerTrait<T>,
+        request: tide::Request<T>,
+        format: export::Format,
+    ) -> tide::Result {
+        let name = request
+            .url()
+            .query_pairs()
+            .find(|(key, _)| key == "table")
+            .map(|(_, value)| value.into_owned());
+        let table = page
+            .tables()
+            .iter()
+            .find(|table| name.as_deref().is_none_or(|name| name == table.field));
+        let table = match table {
+            Some(table) => table,
+            None => {
+                return Ok(tide::Response::builder(404)
+                    .content_type(mime::PLAIN)
+                    .body("No such table\n")
+                    .build())
+            }
+        };
+        let model = match page.generate_json(Request::new(request)).await {
+            Ok(model) => model,
+            Err(e) => {
+                tracing::error!(error = ?e, "Could not generate the model");
+                return Ok(tide::Response::builder(500)
+                    .content_type(mime::PLAIN)
+                    .body(format!("Internal server error\n{:?}\n", e))
+                    .build());
+            }
+        };
+        let rows = &model[table.field];
+        let body: tide::Body = match format {
+            export::Format::Csv => export::csv(table, rows).into(),
+            export::Format::Xlsx => match export::xlsx(table, rows) {
+                Ok(workbook) => workbook.into(),
+                Err(e) => {
+                    tracing::error!(error = ?e, "Could not write the workbook");
+                    return Ok(tide::Response::builder(500)
+                        .content_type(mime::PLAIN)
+                        .body(format!("Internal server error\n{:?}\n", e))
+                        .build());
+                }
+            },
+            _ => export::ndjson(rows).into(),
+        };
+        Ok(tide::Response::builder(200)
+            .content_type(format.mime())
+            .body(body)
+            .build())
+    }
+
+    /// Handles `POST <URL>/actions/<fn>` with a `{ "rows": [<TableRow::id>, ...] }` body.
+    ///
+    /// Responds with `{ "model": <model>, "job": null }` with the model of the page after the
+    /// action, or with a 202 and the URL of the job's status if the action started a [`Job`].
+    async fn invoke(
+        &self,
+        page: &dyn PageHandlerTrait<T>,
+        mut request: tide::Request<T>,
+        function: &str,
+    ) -> tide::Result {
+        // Browsers only send a JSON content type cross-origin after a CORS preflight, which
+        // protects actions against being triggered by forms on other sites
+        if request.content_type() != Some(mime::JSON) {
+            return self.generate_json(
+                415,
+                serde_json::json!({ "error": "expected an application/json body" }),
+            );
+        }
+        let rows = match request.body_json::<serde_json::Value>().await {
+            Ok(body) => body["rows"]
+                .as_array()
+                .map(|rows| {
+                    rows.iter()
+                        .filter_map(|row| row.as_str().map(str::to_string))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default(),
+            Err(e) => {
+                return self.generate_json(400, serde_json::json!({ "error": e.to_string() }))
+            }
+        };
+        let (recording, recorded) = async_std::channel::bounded(1);
+        let invocation = audit::Invocation {
+            page: page.url().to_string(),
+            action: function.to_string(),
+            rows: rows.clone(),
+            recorded,
+        };
+        request.set_ext(invocation.clone());
+        let request = Request::new(request);
+        let user = request.user();
+        let result = page.invoke(request, function, rows).await;
+
+        let entry = invocation.entry(
+            user,
+            match &result {
+                Ok((_, None)) => audit::AuditResult::Ok,
+                Ok((_, Some(job))) => audit::AuditResult::Job(job.url()),
+                Err(e) => audit::AuditResult::Error(format!("{:?}", e)),
+            },
+        );
+        self.audit.record(&entry).await;
+        drop(recording);
+
+        match result {
+            Ok((model, None)) => {
+                self.generate_json(200, serde_json::json!({ "model": model, "job": null }))
+            }
+            Ok((model, Some(job))) => {
+                self.generate_json(202, serde_json::json!({ "model": model, "job": job.url() }))
+            }
+            Err(Error::NotFound(e)) => self.generate_json(404, serde_json::json!({ "error": e })),
+            Err(Error::Conflict(e)) => self.generate_json(409, serde_json::json!({ "error": e })),
+            Err(e) => {
+                tracing::error!(error = ?e, action = function, "Action failed");
+                self.generate_json(500, serde_json::json!({ "error": format!("{:?}", e) }))
+            }
+        }
+    }
+}
+
 struct PageHandler<T, P> {
     _state: PhantomData<T>,
-    _page: PhantomData<P>,
+    cache: cache::PageCache<P>,
 }
 
 impl<T, P> Default for PageHandler<T, P> {
     fn default() -> Self {
         Self {
             _state: PhantomData,
-            _page: PhantomData,
+            cache: cache::PageCache::default(),
         }
     }
 }
 
-impl<T, P> Clone for PageHandler<T, P> {
-    fn clone(&self) -> Self {
-        Self::default()
-    }
-}
-
 impl<T, P> PageHandler<T, P>
 where
     T: Send + Sync + 'static,
@@ -151,11 +892,52 @@ where
     }
 }
 
+impl<T, P> PageHandler<T, P>
+where
+    P: Page<T> + Sync,
+    T: Send + Sync,
+{
+    /// Constructs the page for `request`, or reuses a recent one with `#[page(cache = "...")]`.
+    async fn page(&self, request: Request<T>) -> Result<Arc<P>> {
+        let ttl = match P::CACHE {
+            Some(ttl) => ttl,
+            None => return construct::<T, P>(request).await.map(Arc::new),
+        };
+        // Pages can depend on the query and user, but not on the parameters only the framework
+        // reads
+        let mut query: Vec<_> = request
+            .req
+            .url()
+            .query_pairs()
+            .filter(|(key, _)| key != "refresh" && key != "table")
+            .collect();
+        query.sort();
+        let user = if P::CACHE_PER_USER {
+            request.user()
+        } else {
+            None
+        };
+        let key = serde_json::json!([user, query]).to_string();
+        self.cache
+            .get_or_construct(key, ttl, construct::<T, P>(request))
+            .await
+    }
+}
+
 #[async_trait]
 trait PageHandlerTrait<T>: Send + Sync {
     fn url(&self) -> &str;
-    async fn generate(&self, request: Request<T>) -> Result<String>;
+    fn tables(&self) -> &'static [export::Table];
+    fn actions(&self) -> &'static [openapi::Action];
+    fn schema(&self, generator: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema;
+    async fn generate(&self, request: Request<T>, context: RenderContext) -> Result<String>;
     async fn generate_json(&self, request: Request<T>) -> Result<serde_json::Value>;
+    async fn invoke(
+        &self,
+        request: Request<T>,
+        function: &str,
+        rows: Vec<String>,
+    ) -> Result<(serde_json::Value, Option<Job>)>;
 }
 
 #[async_trait]
@@ -168,21 +950,259 @@ where
         P::URL
     }
 
-    async fn generate(&self, request: Request<T>) -> Result<String> {
-        let p = P::construct(request).await?;
-        Ok(p.html())
+    fn tables(&self) -> &'static [export::Table] {
+        P::TABLES
+    }
+
+    fn actions(&self) -> &'static [openapi::Action] {
+        P::ACTIONS
+    }
+
+    fn schema(&self, generator: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        generator.subschema_for::<P>()
+    }
+
+    async fn generate(&self, request: Request<T>, context: RenderContext) -> Result<String> {
+        let p = self.page(request).await?;
+        Ok(p.html(&context))
     }
 
     async fn generate_json(&self, request: Request<T>) -> Result<serde_json::Value> {
-        let p = P::construct(request).await?;
-        Ok(serde_json::to_value(&p).unwrap())
+        let p = self.page(request).await?;
+        Ok(p.model())
+    }
+
+    async fn invoke(
+        &self,
+        request: Request<T>,
+        function: &str,
+        rows: Vec<String>,
+    ) -> Result<(serde_json::Value, Option<Job>)> {
+        // Actions always see the current state, and change what the cache holds
+        let mut p = construct::<T, P>(request.clone()).await?;
+        let result = p.invoke(function, request, rows).await;
+        if P::CACHE.is_some() {
+            self.cache.clear();
+        }
+        Ok((p.model(), result?))
     }
 }
 
-pub trait Page<T = ()>: DeserializeOwned + Serialize + Constructor<T> + Send + 'static {
+/// Constructs page `P`, recording how long that took in the span and metrics of the request.
+async fn construct<T, P: Page<T>>(request: Request<T>) -> Result<P> {
+    let start = Instant::now();
+    let metrics = request.req.ext::<metrics::Metrics>().cloned();
+    let result = P::construct(request).await;
+    let elapsed = start.elapsed();
+    tracing::Span::current().record("construct_ms", elapsed.as_secs_f64() * 1000.0);
+    if let Some(metrics) = metrics {
+        metrics.observe_construct(P::URL, elapsed);
+    }
+    result
+}
+
+/// The markup of a page, implemented by `#[derive(Page)]`.
+///
+/// This is independent of the server state, so pages can be embedded in each other as a
+/// [`Component`].
+pub trait View: Serialize {
     const URL: &'static str;
+    /// The interval at which the page reloads its model, set by `#[page(refresh = "...")]`.
+    const REFRESH: Option<Duration>;
+    /// How long a constructed page is reused, set by `#[page(cache = "...")]`.
+    const CACHE: Option<Duration>;
+    /// Whether the page is cached separately for every [`Request::user`], set by
+    /// `#[page(cache_per_user)]`.
+    const CACHE_PER_USER: bool;
+    /// The `#[table]` fields of the page, served as CSV at `<URL>.csv` and as NDJSON at
+    /// `<URL>.ndjson`.
+    const TABLES: &'static [export::Table];
+
+    /// Renders the page into an element with the given `id`, filled in with the values of `model`.
+    ///
+    /// The page reloads its model every `interval`, and its components at the intervals
+    /// `context` picks for them.
+    fn render(
+        id: &str,
+        model: &serde_json::Value,
+        interval: Option<Duration>,
+        context: &RenderContext,
+    ) -> String;
+
+    /// The JSON model of the page, as served at `<URL>.json`.
+    fn model(&self) -> serde_json::Value;
 
-    fn html(self) -> String;
+    fn html(&self, context: &RenderContext) -> String;
+}
+
+/// A page served by the framework. Its model also derives [`JsonSchema`], which describes it in
+/// the OpenAPI document at `/openapi.json`.
+pub trait Page<T = ()>:
+    View + Actions<T> + JsonSchema + DeserializeOwned + Constructor<T> + Send + 'static
+{
+}
+
+impl<T, P> Page<T> for P where
+    P: View + Actions<T> + JsonSchema + DeserializeOwned + Constructor<T> + Send + 'static
+{
+}
+
+/// Dispatches the actions of a page, implemented by `#[derive(Page)]`.
+///
+/// Actions are invoked with `POST <URL>/actions/<fn>`, on a freshly constructed page.
+#[doc(hidden)]
+#[async_trait]
+pub trait Actions<T> {
+    /// Every action of the page, for the OpenAPI document.
+    const ACTIONS: &'static [openapi::Action];
+
+    /// Invokes action `function` with the [`TableRow::id`]s of the `rows` it applies to.
+    async fn invoke(
+        &mut self,
+        function: &str,
+        request: Request<T>,
+        rows: Vec<String>,
+    ) -> Result<Option<Job>>;
+}
+
+/// The return types allowed for actions: `Result` or `Result<Job>` for actions that continue
+/// in the background.
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "actions must return `framework::Result` or `framework::Result<framework::Job>`, found `{Self}`"
+)]
+pub trait IntoActionResult {
+    fn into_action_result(self) -> Result<Option<Job>>;
+}
+
+impl IntoActionResult for Result {
+    fn into_action_result(self) -> Result<Option<Job>> {
+        self.map(|()| None)
+    }
+}
+
+impl IntoActionResult for Result<Job> {
+    fn into_action_result(self) -> Result<Option<Job>> {
+        self.map(Some)
+    }
+}
+
+/// Embeds page `P` as a widget in another page with `#[component]`.
+///
+/// The widget loads its own model from `P`'s JSON endpoint, so `P` has to be added to the
+/// [`FrameworkBuilder`] as well.
+pub struct Component<P>(PhantomData<P>);
+
+impl<P> Default for Component<P> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<P> Serialize for Component<P> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_unit()
+    }
+}
+
+impl<P: View> JsonSchema for Component<P> {
+    fn schema_name() -> String {
+        "Component".to_string()
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    // Serialized as `null`, the model is served at the component's own URL
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        let mut schema = schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::Null.into()),
+            ..Default::default()
+        };
+        schema.metadata().description = Some(format!("Loaded from {}.json", P::URL));
+        schema.into()
+    }
+}
+
+impl<'de, P> serde::Deserialize<'de> for Component<P> {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        serde::de::IgnoredAny::deserialize(deserializer).map(|_| Self::default())
+    }
+}
+
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "`#[component]` fields must be a `framework::Component<P>`, found `{Self}`"
+)]
+pub trait ComponentField {
+    const REFRESH: Option<Duration>;
+
+    /// Renders the component, refreshing at the interval `context` picks with `default`.
+    fn render(id: &str, default: Option<Duration>, context: &RenderContext) -> String;
+}
+
+impl<P: View> ComponentField for Component<P> {
+    const REFRESH: Option<Duration> = P::REFRESH;
+
+    fn render(id: &str, default: Option<Duration>, context: &RenderContext) -> String {
+        let interval = context.interval(P::URL, default);
+        // Components load their models without the query of the page they are embedded in
+        let context = RenderContext {
+            query: String::new(),
+            ..context.clone()
+        };
+        P::render(id, &serde_json::Value::Null, interval, &context)
+    }
+}
+
+/// The request a page and its [`Component`]s are rendered for.
+///
+/// It picks their refresh intervals, in order of preference from the `?refresh=` query,
+/// [`FrameworkBuilder::refresh`] and the `#[page(refresh = "...")]` attribute, and keeps the
+/// rest of the query for the export links of the page.
+///
+/// The default picks the attributes and has no query, e.g. to call [`View::html`] in tests.
+#[derive(Clone, Debug, Default)]
+pub struct RenderContext {
+    refresh: Option<Option<Duration>>,
+    configured: Arc<HashMap<String, Option<Duration>>>,
+    query: String,
+}
+
+impl RenderContext {
+    /// The interval of the page at `url`, `default` if it is not overridden.
+    pub fn interval(&self, url: &str, default: Option<Duration>) -> Option<Duration> {
+        match self.refresh {
+            Some(refresh) => refresh,
+            None => self.configured.get(url).copied().unwrap_or(default),
+        }
+    }
+
+    /// The URL the page at `url` reloads its model from, with the current query.
+    pub fn json_url(&self, url: &str) -> String {
+        match self.query.as_str() {
+            "" => format!("{}.json", url),
+            query => format!("{}.json?{}", url, query),
+        }
+    }
+
+    /// The link to the export of `table` of the page at `url` as `extension`, with the current
+    /// query.
+    ///
+    /// The page is constructed from the same query, so it exports the rows it shows. The
+    /// framework does not sort or filter them: they are exported in the order of the model.
+    pub fn export_href(&self, url: &str, extension: &str, table: &str) -> String {
+        match self.query.as_str() {
+            "" => format!("{}.{}?table={}", url, extension, table),
+            query => format!("{}.{}?{}&table={}", url, extension, query, table),
+        }
+    }
 }
 
 pub type Result<T = ()> = std::result::Result<T, Error>;
@@ -193,13 +1213,72 @@ pub trait Constructor<T = ()>: Sized {
 }
 
 pub struct Request<T> {
-    #[allow(dead_code)]
-    req: tide::Request<T>,
+    req: Arc<tide::Request<T>>,
 }
 
 impl<T> Request<T> {
     fn new(req: tide::Request<T>) -> Self {
-        Self { req }
+        Self { req: Arc::new(req) }
+    }
+}
+
+impl<T> Request<T> {
+    /// The state of the app, see [`FrameworkBuilder::with_state`].
+    pub fn state(&self) -> &T {
+        self.req.state()
+    }
+
+    /// The user that sent this request, see [`FrameworkBuilder::basic_auth`] and
+    /// [`FrameworkBuilder::user_header`].
+    pub fn user(&self) -> Option<String> {
+        self.req.ext::<audit::User>().map(|user| user.0.clone())
+    }
+
+    /// Runs `job` on a background task and returns its handle, which an action can return to
+    /// show the job's progress on the page.
+    ///
+    /// Started by an action, how the job ended is added to the audit log before its status
+    /// shows it. Fails if the request was not routed by the framework, which runs the jobs.
+    pub fn spawn_job<F, Fut>(&self, name: impl Into<String>, job: F) -> Result<Job>
+    where
+        F: FnOnce(JobContext) -> Fut,
+        Fut: Future<Output = Result> + Send + 'static,
+    {
+        let audit = self.req.ext::<audit::Audit>().cloned();
+        let invocation = self.req.ext::<audit::Invocation>().cloned();
+        let user = self.user();
+        let jobs = self
+            .req
+            .ext::<jobs::Jobs>()
+            .ok_or_else(|| "Request was not routed by the framework".to_string())?;
+        Ok(jobs.spawn(name.into(), move |context| {
+            let url = context.job().url();
+            let job = job(context);
+            async move {
+                let result = job.await;
+                if let (Some(audit), Some(invocation)) = (audit, invocation) {
+                    let _ = invocation.recorded.recv().await;
+                    let ended = match &result {
+                        Ok(()) => audit::AuditResult::JobSucceeded(url),
+                        Err(e) => audit::AuditResult::JobFailed {
+                            job: url,
+                            error: format!("{:?}", e),
+                        },
+                    };
+                    audit.record(&invocation.entry(user, ended)).await;
+                }
+                result
+            }
+        }))
+    }
+}
+
+// Both the constructor and the actions of a page receive the request
+impl<T> Clone for Request<T> {
+    fn clone(&self) -> Self {
+        Self {
+            req: self.req.clone(),
+        }
     }
 }
 
@@ -219,6 +1298,10 @@ pub enum Error {
     Io(std::io::Error),
     String(String),
     Tide(std::io::Error),
+    /// Responds with a 404, e.g. for an unknown action.
+    NotFound(String),
+    /// Responds with a 409, e.g. for an action that is disabled for its row.
+    Conflict(String),
 }
 
 impl From<std::io::Error> for Error {
@@ -232,6 +1315,10 @@ impl From<String> for Error {
     }
 }
 
+/// A row of a `#[table]` field.
 pub trait TableRow: Clone {
+    /// Identifies this row across reloads, so the client can update it in place.
+    ///
+    /// This should be stable while the row's values change.
     fn id(&self) -> String;
 }
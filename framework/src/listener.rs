@@ -0,0 +1,366 @@
+//! Accepts HTTP and HTTPS connections, see [`FrameworkBuilder::bind`](crate::FrameworkBuilder::bind)
+//! and [`FrameworkBuilder::bind_tls`](crate::FrameworkBuilder::bind_tls).
+//!
+//! Connections are counted until they close, so a shutting down [`Server`](crate::Server) can
+//! wait for them. Once it shuts down, connections are closed as soon as they are idle.
+
+use crate::server::{Notify, ShutdownHandle};
+use async_h1::server::ConnectionStatus;
+use async_std::io::{self, ReadExt};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::stream::StreamExt;
+use futures_lite::AsyncWriteExt;
+use futures_rustls::rustls::crypto::ring;
+use futures_rustls::rustls::pki_types::pem::PemObject;
+use futures_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use futures_rustls::rustls::ServerConfig;
+use futures_rustls::TlsAcceptor;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tide::listener::{ListenInfo, Listener, ToListener};
+
+/// The open connections of a server, shared by its listeners.
+#[derive(Clone, Default)]
+pub(crate) struct Connections {
+    open: Arc<AtomicUsize>,
+    closed: Notify,
+}
+
+/// Counts a connection as open until it is dropped.
+struct OpenConnection(Connections);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::Relaxed);
+        self.0.closed.notify();
+    }
+}
+
+impl Connections {
+    fn open(&self) -> OpenConnection {
+        self.open.fetch_add(1, Ordering::Relaxed);
+        OpenConnection(self.clone())
+    }
+
+    /// The number of connections that are open right now.
+    pub(crate) fn count(&self) -> usize {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// Completes when a connection closes.
+    pub(crate) async fn closed(&self) {
+        self.closed.notified().await
+    }
+}
+
+/// The PEM files of the certificate chain and private key of an HTTPS listener.
+#[derive(Clone, Debug)]
+pub(crate) struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Listens for HTTP, or HTTPS with [`TlsFiles`], on an address.
+pub(crate) struct HttpListener<T> {
+    addresses: Vec<SocketAddr>,
+    tls: Option<TlsFiles>,
+    shutdown: ShutdownHandle,
+    connections: Connections,
+    bound: Option<Bound<T>>,
+}
+
+struct Bound<T> {
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    server: tide::Server<T>,
+}
+
+impl<T> HttpListener<T> {
+    /// Listens on the first of `addresses` that can be bound to, like
+    /// [`TcpListener::bind`](std::net::TcpListener::bind).
+    pub(crate) fn new(
+        addresses: Vec<SocketAddr>,
+        tls: Option<TlsFiles>,
+        shutdown: ShutdownHandle,
+        connections: Connections,
+    ) -> Self {
+        Self {
+            addresses,
+            tls,
+            shutdown,
+            connections,
+            bound: None,
+        }
+    }
+
+    fn scheme(&self) -> &'static str {
+        match self.tls {
+            Some(_) => "https",
+            None => "http",
+        }
+    }
+}
+
+/// Reads the certificate chain from the PEM file `cert` and the private key from `key`.
+fn server_config(cert: &Path, key: &Path) -> io::Result<ServerConfig> {
+    let invalid = |path: &Path, e: &dyn std::fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: {}", path.display(), e),
+        )
+    };
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(cert, &e))?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid(key, &e))?;
+    // Picks the provider, so apps that enable another one for rustls do not make this ambiguous
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid(cert, &e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid(cert, &e))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// A connection of an [`HttpListener`].
+struct Connection<T> {
+    server: tide::Server<T>,
+    stream: TcpStream,
+    shutdown: ShutdownHandle,
+    local_addr: Option<String>,
+    peer_addr: Option<String>,
+}
+
+impl<T: Clone + Send + Sync + 'static> Connection<T> {
+    async fn serve(self, acceptor: Option<TlsAcceptor>, _open: OpenConnection) {
+        let result = match acceptor {
+            Some(acceptor) => {
+                let handshake = async { Some(acceptor.accept(self.stream.clone()).await) };
+                let shutdown = async {
+                    self.shutdown.wait().await;
+                    None
+                };
+                let stream = match futures_lite::future::or(handshake, shutdown).await {
+                    Some(Ok(stream)) => stream,
+                    Some(Err(e)) => {
+                        tracing::debug!(error = %e, peer = ?self.peer_addr, "TLS handshake failed");
+                        return;
+                    }
+                    None => return,
+                };
+                // Reads and writes of the connection take turns, but async-h1 needs a handle
+                // for each
+                let mut stream = async_dup::Arc::new(async_dup::Mutex::new(stream));
+                let result = self.serve_requests(stream.clone(), "https").await;
+                // Tells the client the response is complete, rather than cut off
+                let _ = stream.close().await;
+                result
+            }
+            None => self.serve_requests(self.stream.clone(), "http").await,
+        };
+        if let Err(e) = result {
+            tracing::debug!(error = %e, peer = ?self.peer_addr, "Could not serve a connection");
+        }
+    }
+
+    /// Serves the requests on `io` until the client closes the connection, or until the server
+    /// shuts down. Requests that are being served then get a `Connection: close` response, and
+    /// connections that wait for their next request are closed right away.
+    async fn serve_requests<RW>(&self, io: RW, scheme: &'static str) -> tide::http::Result<()>
+    where
+        RW: io::Read + io::Write + Clone + Send + Sync + Unpin + 'static,
+    {
+        let endpoint = |mut request: tide::http::Request| async move {
+            let _ = request.url_mut().set_scheme(scheme);
+            request.set_local_addr(self.local_addr.as_ref());
+            request.set_peer_addr(self.peer_addr.as_ref());
+            let mut response: tide::http::Response = self.server.respond(request).await?;
+            if self.shutdown.is_shut_down() {
+                response.insert_header("Connection", "close");
+            }
+            Ok(response)
+        };
+        let mut io = Peekable::new(io);
+        let mut server = async_h1::server::Server::new(io.clone(), endpoint);
+        loop {
+            // The first byte of the next request, or the client closing the connection
+            let request = io.peek();
+            let shutdown = async {
+                self.shutdown.wait().await;
+                Ok(false)
+            };
+            if !futures_lite::future::or(request, shutdown).await? {
+                return Ok(());
+            }
+            if server.accept_one().await? == ConnectionStatus::Close {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// The stream of a connection, which can wait for the next request without consuming it.
+///
+/// Waiting for the socket to become readable is not enough, rustls may have read the request
+/// into its buffer along with the end of the handshake. A byte read by [`Peekable::peek`] is
+/// kept and returned by the next read instead.
+#[derive(Clone)]
+struct Peekable<RW> {
+    io: RW,
+    peeked: Arc<Mutex<Option<u8>>>,
+}
+
+impl<RW: io::Read + Unpin> Peekable<RW> {
+    fn new(io: RW) -> Self {
+        Self {
+            io,
+            peeked: Arc::default(),
+        }
+    }
+
+    /// Waits until the client sends data, false if it closes the connection instead.
+    async fn peek(&mut self) -> io::Result<bool> {
+        if lock(&self.peeked).is_some() {
+            return Ok(true);
+        }
+        let mut byte = [0];
+        if self.io.read(&mut byte).await? == 0 {
+            return Ok(false);
+        }
+        *lock(&self.peeked) = Some(byte[0]);
+        Ok(true)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<RW: io::Read + Unpin> io::Read for Peekable<RW> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if let (Some(first), Some(byte)) = (buf.first_mut(), lock(&this.peeked).take()) {
+            *first = byte;
+            return Poll::Ready(Ok(1));
+        }
+        Pin::new(&mut this.io).poll_read(cx, buf)
+    }
+}
+
+impl<RW: io::Write + Unpin> io::Write for Peekable<RW> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_close(cx)
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> ToListener<T> for HttpListener<T> {
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self> {
+        Ok(self)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Clone + Send + Sync + 'static> Listener<T> for HttpListener<T> {
+    async fn bind(&mut self, server: tide::Server<T>) -> io::Result<()> {
+        let acceptor = match &self.tls {
+            Some(tls) => Some(TlsAcceptor::from(Arc::new(server_config(
+                &tls.cert, &tls.key,
+            )?))),
+            None => None,
+        };
+        let listener = TcpListener::bind(&self.addresses[..]).await?;
+        self.bound = Some(Bound {
+            listener,
+            acceptor,
+            server,
+        });
+        Ok(())
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        let bound = self.bound.as_ref().expect("Listener was not bound");
+        let mut incoming = bound.listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => {
+                    let connection = Connection {
+                        server: bound.server.clone(),
+                        local_addr: stream.local_addr().ok().map(|addr| addr.to_string()),
+                        peer_addr: stream.peer_addr().ok().map(|addr| addr.to_string()),
+                        stream,
+                        shutdown: self.shutdown.clone(),
+                    };
+                    let open = self.connections.open();
+                    async_std::task::spawn(connection.serve(bound.acceptor.clone(), open));
+                }
+                // The client gave up before it was accepted
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => {}
+                Err(e) => {
+                    // E.g. out of file descriptors, which closing connections frees up
+                    tracing::error!(error = %e, "Could not accept a connection");
+                    async_std::task::sleep(Duration::from_millis(500)).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        vec![ListenInfo::new(
+            self.to_string(),
+            "tcp".to_string(),
+            self.tls.is_some(),
+        )]
+    }
+}
+
+impl<T> std::fmt::Display for HttpListener<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bound = self.bound.as_ref();
+        match bound.and_then(|bound| bound.listener.local_addr().ok()) {
+            Some(address) => write!(f, "{}://{}", self.scheme(), address),
+            None => write!(f, "{}://{:?}", self.scheme(), self.addresses),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for HttpListener<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpListener")
+            .field("addresses", &self.addresses)
+            .field("tls", &self.tls)
+            .finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn test_server_config_errors() {
+    let missing = Path::new("/nonexistent/cert.pem");
+    let error = server_config(missing, missing).unwrap_err();
+    assert!(error.to_string().contains("cert.pem"), "{}", error);
+}
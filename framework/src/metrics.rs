@@ -0,0 +1,325 @@
+//! Metrics of the requests handled by the framework, served in the Prometheus text format at
+//! `/metrics`.
+
+use crate::server::Notify;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The header with which script.js identifies a page it refreshes, see [`Metrics::observe_poll`].
+pub(crate) const CLIENT_HEADER: &str = "X-Framework-Client";
+/// The header with the interval in milliseconds at which script.js refreshes the page.
+pub(crate) const REFRESH_HEADER: &str = "X-Framework-Refresh";
+
+/// The most live clients that are tracked, so made up client ids can not use up memory.
+const MAX_CLIENTS: usize = 10_000;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, page: &str) {
+        let page = escape_label(page);
+        for (bucket, bound) in self.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{page=\"{}\",le=\"{}\"}} {}",
+                name, page, bound, bucket
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{page=\"{}\",le=\"+Inf\"}} {}",
+            name, page, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{page=\"{}\"}} {}", name, page, self.sum);
+        let _ = writeln!(out, "{}_count{{page=\"{}\"}} {}", name, page, self.count);
+    }
+}
+
+#[derive(Default)]
+struct State {
+    requests: BTreeMap<(String, u16), u64>,
+    errors: BTreeMap<String, u64>,
+    latency: BTreeMap<String, Histogram>,
+    construct: BTreeMap<String, Histogram>,
+    /// Until when each client of a page counts as live, by page and client id.
+    clients: HashMap<(String, String), Instant>,
+}
+
+/// The metrics of a server, shared with every request through its extensions.
+#[derive(Clone, Default)]
+pub(crate) struct Metrics {
+    active: Arc<AtomicI64>,
+    finished: Notify,
+    state: Arc<Mutex<State>>,
+}
+
+/// Counts a request as active until it is dropped.
+pub(crate) struct ActiveRequest(Arc<AtomicI64>, Notify);
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+        self.1.notify();
+    }
+}
+
+impl Metrics {
+    pub(crate) fn start_request(&self) -> ActiveRequest {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        ActiveRequest(self.active.clone(), self.finished.clone())
+    }
+
+    /// The number of requests that are being handled right now.
+    pub(crate) fn active_requests(&self) -> i64 {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Completes when a request finishes.
+    pub(crate) async fn finished(&self) {
+        self.finished.notified().await
+    }
+
+    pub(crate) fn observe_request(&self, page: &str, status: u16, latency: Duration) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        *state
+            .requests
+            .entry((page.to_string(), status))
+            .or_default() += 1;
+        if status >= 500 {
+            *state.errors.entry(page.to_string()).or_default() += 1;
+        }
+        state
+            .latency
+            .entry(page.to_string())
+            .or_default()
+            .observe(latency);
+    }
+
+    /// Counts `client` as a live client of `page` for twice its `refresh` interval, so it stays
+    /// counted while it keeps polling, even if a poll is a little late.
+    pub(crate) fn observe_poll(&self, page: &str, client: &str, refresh: Duration) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.clients.retain(|_, until| *until > now);
+        let key = (page.to_string(), client.to_string());
+        if state.clients.len() < MAX_CLIENTS || state.clients.contains_key(&key) {
+            state.clients.insert(key, now + refresh * 2);
+        }
+    }
+
+    pub(crate) fn observe_construct(&self, page: &str, duration: Duration) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state
+            .construct
+            .entry(page.to_string())
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut out = String::new();
+
+        out += "# HELP framework_requests_total Requests handled, by page and status.\n";
+        out += "# TYPE framework_requests_total counter\n";
+        for ((page, status), count) in &state.requests {
+            let _ = writeln!(
+                out,
+                "framework_requests_total{{page=\"{}\",status=\"{}\"}} {}",
+                escape_label(page),
+                status,
+                count
+            );
+        }
+
+        out += "# HELP framework_request_errors_total Requests that failed with a 5xx status.\n";
+        out += "# TYPE framework_request_errors_total counter\n";
+        for (page, count) in &state.errors {
+            let _ = writeln!(
+                out,
+                "framework_request_errors_total{{page=\"{}\"}} {}",
+                escape_label(page),
+                count
+            );
+        }
+
+        out += "# HELP framework_request_duration_seconds Time to handle a request.\n";
+        out += "# TYPE framework_request_duration_seconds histogram\n";
+        for (page, histogram) in &state.latency {
+            histogram.write(&mut out, "framework_request_duration_seconds", page);
+        }
+
+        out += "# HELP framework_construct_duration_seconds Time to construct a page.\n";
+        out += "# TYPE framework_construct_duration_seconds histogram\n";
+        for (page, histogram) in &state.construct {
+            histogram.write(&mut out, "framework_construct_duration_seconds", page);
+        }
+
+        out += "# HELP framework_live_clients Browsers refreshing a page, that polled it within \
+                twice their refresh interval.\n";
+        out += "# TYPE framework_live_clients gauge\n";
+        let now = Instant::now();
+        let mut clients = BTreeMap::<&str, u64>::new();
+        for ((page, _), until) in &state.clients {
+            if *until > now {
+                *clients.entry(page).or_default() += 1;
+            }
+        }
+        for (page, count) in clients {
+            let _ = writeln!(
+                out,
+                "framework_live_clients{{page=\"{}\"}} {}",
+                escape_label(page),
+                count
+            );
+        }
+
+        out += "# HELP framework_active_requests Requests that are being handled right now.\n";
+        out += "# TYPE framework_active_requests gauge\n";
+        let _ = writeln!(out, "framework_active_requests {}", self.active_requests());
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn test_render_metrics() {
+    let metrics = Metrics::default();
+    let active = metrics.start_request();
+    metrics.observe_request("/vms", 200, Duration::from_millis(20));
+    metrics.observe_request("/vms", 200, Duration::from_millis(300));
+    metrics.observe_request("/vms", 500, Duration::from_secs(20));
+    metrics.observe_construct("/vms", Duration::from_millis(7));
+    metrics.observe_request("/a\"b", 404, Duration::ZERO);
+    metrics.observe_poll("/vms", "a", Duration::from_secs(5));
+    metrics.observe_poll("/vms", "a", Duration::from_secs(5));
+    metrics.observe_poll("/vms", "b", Duration::from_secs(5));
+    metrics.observe_poll("/uptime", "a", Duration::from_secs(1));
+    metrics.observe_poll("/gone", "c", Duration::from_millis(1));
+    std::thread::sleep(Duration::from_millis(5));
+
+    let out = metrics.render();
+    let has = |line: &str| out.lines().any(|l| l == line);
+    assert!(
+        has("framework_requests_total{page=\"/vms\",status=\"200\"} 2"),
+        "{}",
+        out
+    );
+    assert!(
+        has("framework_requests_total{page=\"/a\\\"b\",status=\"404\"} 1"),
+        "{}",
+        out
+    );
+    assert!(
+        has("framework_request_errors_total{page=\"/vms\"} 1"),
+        "{}",
+        out
+    );
+    assert!(
+        has("framework_request_duration_seconds_bucket{page=\"/vms\",le=\"0.025\"} 1"),
+        "{}",
+        out
+    );
+    assert!(
+        has("framework_request_duration_seconds_bucket{page=\"/vms\",le=\"0.5\"} 2"),
+        "{}",
+        out
+    );
+    assert!(
+        has("framework_request_duration_seconds_bucket{page=\"/vms\",le=\"+Inf\"} 3"),
+        "{}",
+        out
+    );
+    assert!(
+        has("framework_request_duration_seconds_count{page=\"/vms\"} 3"),
+        "{}",
+        out
+    );
+    assert!(
+        has("framework_construct_duration_seconds_bucket{page=\"/vms\",le=\"0.01\"} 1"),
+        "{}",
+        out
+    );
+    assert!(has("framework_active_requests 1"), "{}", out);
+    assert!(has("framework_live_clients{page=\"/vms\"} 2"), "{}", out);
+    assert!(has("framework_live_clients{page=\"/uptime\"} 1"), "{}", out);
+    assert!(!out.contains("/gone"), "{}", out);
+
+    drop(active);
+    assert_eq!(metrics.active_requests(), 0);
+}
+
+#[test]
+fn test_live_clients() {
+    async_std::task::block_on(async {
+        let builder = crate::FrameworkBuilder::default().audit_page(true);
+        let client = crate::testing::TestClient::new(builder).unwrap();
+        let poll = |path: &str, id: &str| {
+            let mut request = client.request(tide::http::Method::Get, path);
+            request.insert_header(CLIENT_HEADER, id);
+            request.insert_header(REFRESH_HEADER, "10000");
+            client.send(request)
+        };
+        poll("/audit.json", "a").await;
+        poll("/audit.json", "b").await;
+        poll("/audit.json", &"x".repeat(65)).await;
+        // Neither polls of a page's model, nor successful
+        poll("/audit", "c").await;
+        poll("/missing.json", "d").await;
+        client.get("/audit.json").await;
+
+        let metrics = client.get("/metrics").await;
+        let live: Vec<_> = metrics
+            .text()
+            .lines()
+            .filter(|line| line.starts_with("framework_live_clients{"))
+            .collect();
+        assert_eq!(live, ["framework_live_clients{page=\"/audit\"} 2"]);
+    });
+}
+
+/// Collides with `/metrics`.
+#[cfg(test)]
+#[derive(crate::Page, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[page(path = "/metrics")]
+struct Graphs {
+    name: String,
+}
+
+#[test]
+fn test_metrics_page_collision() {
+    let builder = crate::FrameworkBuilder::default().add_page::<Graphs>();
+    let error = crate::testing::TestClient::new(builder).err().unwrap();
+    assert!(format!("{:?}", error).contains("/metrics"), "{:?}", error);
+}
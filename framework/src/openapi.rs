@@ -0,0 +1,321 @@
+//! The OpenAPI document served at `/openapi.json`, describing the JSON model, table exports and
+//! actions of every page, with the JSON Schemas of their models.
+
+use crate::PageHandlerTrait;
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Map, Value};
+
+/// An `#[action]` or `#[bulk_action]` of a page, as listed in
+/// [`Actions::ACTIONS`](crate::Actions::ACTIONS).
+#[derive(Debug)]
+pub struct Action {
+    pub function: &'static str,
+    pub name: &'static str,
+    /// Whether it applies to all selected rows instead of exactly one.
+    pub bulk: bool,
+}
+
+/// Builds the OpenAPI 3.0 document of `pages`.
+pub(crate) fn document<T>(pages: &[Box<dyn PageHandlerTrait<T>>]) -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+    let mut models = Vec::new();
+    for page in pages {
+        let url = page.url();
+        let model = serde_json::to_value(page.schema(&mut generator)).unwrap();
+        paths.insert(
+            format!("{}.json", url),
+            json!({
+                "get": {
+                    "summary": format!("The model of {}", url),
+                    "responses": {
+                        "200": json_response("The model", model.clone()),
+                        "500": error_response(),
+                    },
+                },
+            }),
+        );
+        if !page.tables().is_empty() {
+            let tables: Vec<_> = page.tables().iter().map(|table| table.field).collect();
+            for (extension, mime) in [
+                ("csv", "text/csv"),
+                ("ndjson", "application/x-ndjson"),
+                (
+                    "xlsx",
+                    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+                ),
+            ] {
+                paths.insert(
+                    format!("{}.{}", url, extension),
+                    json!({
+                        "get": {
+                            "summary": format!("The rows of a table of {}", url),
+                            "parameters": [{
+                                "name": "table",
+                                "in": "query",
+                                "description": "The table to export, the first one by default",
+                                "schema": { "type": "string", "enum": tables },
+                            }],
+                            "responses": {
+                                "200": {
+                                    "description": "The rows",
+                                    "content": { mime: { "schema": { "type": "string" } } },
+                                },
+                                "404": { "description": "The page has no such table" },
+                            },
+                        },
+                    }),
+                );
+            }
+        }
+        for action in page.actions() {
+            paths.insert(
+                format!("{}/actions/{}", url, action.function),
+                action_path(url, action, &model),
+            );
+        }
+        models.push((model, page.tables()));
+    }
+    paths.insert(
+        "/jobs/{id}.json".to_string(),
+        json!({
+            "get": {
+                "summary": "The status of a job started by an action",
+                "parameters": [{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer" },
+                }],
+                "responses": {
+                    "200": json_response("The status", schema_ref("Job")),
+                    "404": error_response(),
+                },
+            },
+        }),
+    );
+
+    let mut schemas = serde_json::to_value(generator.take_definitions()).unwrap();
+    for (model, tables) in models {
+        // The rows of tables are sent with their `TableRow::id`, and which actions they allow
+        let name = model["$ref"]
+            .as_str()
+            .and_then(|reference| reference.strip_prefix("#/components/schemas/"));
+        let properties = match name {
+            Some(name) => &mut schemas[name]["properties"],
+            None => continue,
+        };
+        for table in tables {
+            let items = &mut properties[table.field]["items"];
+            if !items.is_null() {
+                *items = json!({ "allOf": [items.take(), schema_ref("TableRow")] });
+            }
+        }
+    }
+    schemas["TableRow"] = json!({
+        "type": "object",
+        "required": ["_id"],
+        "properties": {
+            "_id": { "type": "string", "description": "The id to invoke actions with" },
+            "_enabled": {
+                "type": "object",
+                "description": "Whether the actions with an `enabled_if` apply to the row",
+                "additionalProperties": { "type": "boolean" },
+            },
+        },
+    });
+    schemas["ActionRequest"] = json!({
+        "type": "object",
+        "required": ["rows"],
+        "properties": {
+            "rows": { "type": "array", "items": { "type": "string" } },
+        },
+    });
+    schemas["Job"] = json!({
+        "type": "object",
+        "required": ["id", "name", "state", "progress", "log"],
+        "properties": {
+            "id": { "type": "integer" },
+            "name": { "type": "string" },
+            "state": { "type": "string", "enum": ["running", "succeeded", "failed"] },
+            "progress": { "type": "number", "nullable": true },
+            "log": { "type": "array", "items": { "type": "string" } },
+            "error": { "type": "string", "nullable": true },
+        },
+    });
+    schemas["Error"] = json!({
+        "type": "object",
+        "required": ["error"],
+        "properties": { "error": { "type": "string" } },
+    });
+
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": "framework", "version": "1" },
+        "paths": paths,
+        "components": { "schemas": schemas },
+    })
+}
+
+fn action_path(url: &str, action: &Action, model: &Value) -> Value {
+    let rows = if action.bulk {
+        "the `_id`s of the selected rows"
+    } else {
+        "the `_id` of exactly one row"
+    };
+    let result = |description: &str, job: Value| {
+        json_response(
+            description,
+            json!({
+                "type": "object",
+                "required": ["model", "job"],
+                "properties": { "model": model, "job": job },
+            }),
+        )
+    };
+    json!({
+        "post": {
+            "summary": format!("{} on {}", action.name, url),
+            "description": format!("Invokes `{}` with {}.", action.function, rows),
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": schema_ref("ActionRequest") } },
+            },
+            "responses": {
+                "200": result(
+                    "The model after the action",
+                    json!({ "type": "string", "nullable": true, "enum": [null] }),
+                ),
+                "202": result(
+                    "The model after starting a job, and the URL of its status",
+                    json!({ "type": "string" }),
+                ),
+                "400": error_response(),
+                "404": error_response(),
+                "409": error_response(),
+                "415": error_response(),
+                "500": error_response(),
+            },
+        },
+    })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn error_response() -> Value {
+    json_response("An error", schema_ref("Error"))
+}
+
+#[cfg(test)]
+#[derive(crate::Page, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[page(path = "/lights")]
+struct Lights {
+    building: String,
+    #[table(selectable)]
+    #[column(field = "room")]
+    #[action(name = "Switch", fn = "switch")]
+    #[bulk_action(name = "All off", fn = "all_off")]
+    lights: Vec<Light>,
+}
+
+#[cfg(test)]
+#[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+struct Light {
+    room: String,
+    on: bool,
+}
+
+#[cfg(test)]
+impl crate::TableRow for Light {
+    fn id(&self) -> String {
+        self.room.clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl crate::Constructor for Lights {
+    async fn construct(_: crate::Request<()>) -> crate::Result<Self> {
+        Ok(Self {
+            building: "Main".to_string(),
+            lights: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+impl Lights {
+    async fn switch(&mut self, _: crate::Request<()>, _: Light) -> crate::Result {
+        Ok(())
+    }
+
+    async fn all_off(&mut self, _: crate::Request<()>, _: Vec<Light>) -> crate::Result {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_openapi() {
+    async_std::task::block_on(async {
+        let builder = crate::FrameworkBuilder::default()
+            .add_page::<Lights>()
+            .audit_page(true);
+        let client = crate::testing::TestClient::new(builder).unwrap();
+        let document = client.get("/openapi.json").await.json();
+        assert_eq!(document["openapi"], "3.0.3");
+
+        let paths = &document["paths"];
+        let model = &paths["/lights.json"]["get"]["responses"]["200"]["content"]
+            ["application/json"]["schema"];
+        assert_eq!(model["$ref"], "#/components/schemas/Lights");
+        assert_eq!(
+            paths["/lights.csv"]["get"]["parameters"][0]["schema"]["enum"],
+            json!(["lights"])
+        );
+        let switch = &paths["/lights/actions/switch"]["post"];
+        assert_eq!(switch["summary"], "Switch on /lights");
+        assert!(switch["description"]
+            .as_str()
+            .unwrap()
+            .contains("exactly one row"));
+        let all_off = &paths["/lights/actions/all_off"]["post"];
+        assert!(all_off["description"]
+            .as_str()
+            .unwrap()
+            .contains("selected rows"));
+        assert!(paths["/audit.json"].is_object());
+        assert!(paths["/jobs/{id}.json"].is_object());
+
+        let schemas = &document["components"]["schemas"];
+        let lights = &schemas["Lights"]["properties"];
+        assert_eq!(lights["building"]["type"], "string");
+        let rows = &lights["lights"]["items"]["allOf"];
+        assert_eq!(rows[0]["$ref"], "#/components/schemas/Light");
+        assert_eq!(rows[1]["$ref"], "#/components/schemas/TableRow");
+        assert_eq!(schemas["Light"]["properties"]["on"]["type"], "boolean");
+    });
+}
+
+/// Takes the JSON route of the OpenAPI document.
+#[cfg(test)]
+#[derive(crate::Page, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[page(path = "/openapi")]
+struct Specs {
+    name: String,
+}
+
+#[test]
+fn test_openapi_page_collision() {
+    let builder = crate::FrameworkBuilder::default().add_page::<Specs>();
+    let error = crate::testing::TestClient::new(builder).err().unwrap();
+    assert!(format!("{:?}", error).contains("/openapi"), "{:?}", error);
+}
@@ -0,0 +1,158 @@
+//! Security related headers that are added to every response.
+
+use tide::http::headers::HeaderName;
+
+/// The Content-Security-Policy used by default, without the `frame-ancestors` directive.
+///
+/// Only the framework's own `/static/script.js` may run, inline scripts and `eval` are blocked.
+pub const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; \
+    script-src 'self'; \
+    style-src 'self'; \
+    img-src 'self' data:; \
+    connect-src 'self'; \
+    object-src 'none'; \
+    base-uri 'none'; \
+    form-action 'self'";
+
+/// Configures the security headers sent by the framework, see
+/// [`FrameworkBuilder::security_headers`](crate::FrameworkBuilder::security_headers).
+///
+/// Setting a value to `None` stops the framework from sending that header.
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    /// `Content-Security-Policy`, [`frame_ancestors`](Self::frame_ancestors) is appended to it.
+    pub content_security_policy: Option<String>,
+    /// The `frame-ancestors` CSP directive, e.g. `'none'`, `'self'` or a list of origins.
+    ///
+    /// `'none'` and `'self'` are also sent as `X-Frame-Options` for older browsers.
+    pub frame_ancestors: Option<String>,
+    /// `X-Content-Type-Options`
+    pub content_type_options: Option<String>,
+    /// `Referrer-Policy`
+    pub referrer_policy: Option<String>,
+    /// `Strict-Transport-Security`, only sent by servers bound with
+    /// [`FrameworkBuilder::bind_tls`](crate::FrameworkBuilder::bind_tls).
+    pub strict_transport_security: Option<String>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            content_security_policy: Some(DEFAULT_CONTENT_SECURITY_POLICY.to_string()),
+            frame_ancestors: Some("'none'".to_string()),
+            content_type_options: Some("nosniff".to_string()),
+            referrer_policy: Some("same-origin".to_string()),
+            strict_transport_security: Some("max-age=31536000".to_string()),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    /// Does not send any security headers.
+    pub fn none() -> Self {
+        Self {
+            content_security_policy: None,
+            frame_ancestors: None,
+            content_type_options: None,
+            referrer_policy: None,
+            strict_transport_security: None,
+        }
+    }
+
+    pub fn content_security_policy(mut self, policy: Option<impl Into<String>>) -> Self {
+        self.content_security_policy = policy.map(Into::into);
+        self
+    }
+
+    pub fn frame_ancestors(mut self, ancestors: Option<impl Into<String>>) -> Self {
+        self.frame_ancestors = ancestors.map(Into::into);
+        self
+    }
+
+    pub fn content_type_options(mut self, options: Option<impl Into<String>>) -> Self {
+        self.content_type_options = options.map(Into::into);
+        self
+    }
+
+    pub fn referrer_policy(mut self, policy: Option<impl Into<String>>) -> Self {
+        self.referrer_policy = policy.map(Into::into);
+        self
+    }
+
+    /// E.g. `"max-age=31536000; includeSubDomains"`. Browsers then only connect to this host over
+    /// HTTPS for the next year, so only enable `includeSubDomains` when every subdomain has TLS.
+    pub fn strict_transport_security(mut self, policy: Option<impl Into<String>>) -> Self {
+        self.strict_transport_security = policy.map(Into::into);
+        self
+    }
+
+    /// The headers to send, in the order they are added to the response.
+    pub fn headers(&self) -> Vec<(HeaderName, String)> {
+        let mut headers = Vec::new();
+        let csp = match (&self.content_security_policy, &self.frame_ancestors) {
+            (Some(policy), Some(ancestors)) => {
+                Some(format!("{}; frame-ancestors {}", policy, ancestors))
+            }
+            (Some(policy), None) => Some(policy.clone()),
+            (None, Some(ancestors)) => Some(format!("frame-ancestors {}", ancestors)),
+            (None, None) => None,
+        };
+        if let Some(csp) = csp {
+            headers.push(("Content-Security-Policy".into(), csp));
+        }
+        match self.frame_ancestors.as_deref() {
+            Some("'none'") => headers.push(("X-Frame-Options".into(), "DENY".to_string())),
+            Some("'self'") => headers.push(("X-Frame-Options".into(), "SAMEORIGIN".to_string())),
+            _ => {}
+        }
+        if let Some(options) = &self.content_type_options {
+            headers.push(("X-Content-Type-Options".into(), options.clone()));
+        }
+        if let Some(policy) = &self.referrer_policy {
+            headers.push(("Referrer-Policy".into(), policy.clone()));
+        }
+        if let Some(policy) = &self.strict_transport_security {
+            headers.push(("Strict-Transport-Security".into(), policy.clone()));
+        }
+        headers
+    }
+
+    pub(crate) fn apply(&self, response: &mut tide::Response) {
+        for (name, value) in self.headers() {
+            if response.header(&name).is_none() {
+                response.insert_header(name, value);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_default_security_headers() {
+    let headers = SecurityHeaders::default().headers();
+    let get = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n.as_str().eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    };
+    let csp = get("Content-Security-Policy").unwrap();
+    assert!(csp.starts_with("default-src 'self';"), "{}", csp);
+    assert!(!csp.contains("unsafe-inline"), "{}", csp);
+    assert!(!csp.contains("unsafe-eval"), "{}", csp);
+    assert!(csp.ends_with("; frame-ancestors 'none'"), "{}", csp);
+    assert_eq!(get("X-Frame-Options"), Some("DENY"));
+    assert_eq!(get("X-Content-Type-Options"), Some("nosniff"));
+    assert_eq!(get("Referrer-Policy"), Some("same-origin"));
+    assert_eq!(get("Strict-Transport-Security"), Some("max-age=31536000"));
+}
+
+#[test]
+fn test_configured_security_headers() {
+    let headers = SecurityHeaders::none()
+        .frame_ancestors(Some("https://example.com"))
+        .headers();
+    assert_eq!(headers.len(), 1);
+    assert_eq!(headers[0].1, "frame-ancestors https://example.com");
+
+    assert!(SecurityHeaders::none().headers().is_empty());
+}
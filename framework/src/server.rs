@@ -0,0 +1,547 @@
+//! The lifecycle of a running server, see [`FrameworkBuilder::bind`](crate::FrameworkBuilder::bind).
+
+use crate::{jobs::Jobs, listener::Connections, metrics::Metrics, Error, Result};
+use async_std::channel::{bounded, Receiver, Sender};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tide::listener::Listener;
+
+/// A server that is bound to its listeners, but does not accept connections until
+/// [`Server::run`] is called.
+pub struct Server<T> {
+    pub(crate) listener: Box<dyn Listener<T>>,
+    /// Redirects plain HTTP to HTTPS, see
+    /// [`FrameworkBuilder::redirect_http`](crate::FrameworkBuilder::redirect_http).
+    pub(crate) redirect: Option<Box<dyn Listener<()>>>,
+    pub(crate) shutdown: ShutdownHandle,
+    pub(crate) shutdown_on_signal: bool,
+    pub(crate) shutdown_timeout: Duration,
+    pub(crate) jobs: Jobs,
+    pub(crate) metrics: Metrics,
+    pub(crate) connections: Connections,
+}
+
+impl<T: Clone + Send + Sync + 'static> Server<T> {
+    /// The addresses the server listens on, e.g. `http://127.0.0.1:8080`.
+    ///
+    /// When binding to port 0, this contains the port that was picked by the OS. TLS addresses
+    /// start with `https://`, and are followed by the address that redirects to them.
+    pub fn addresses(&self) -> Vec<String> {
+        let redirect = self.redirect.iter().flat_map(|redirect| redirect.info());
+        self.listener
+            .info()
+            .into_iter()
+            .chain(redirect)
+            .map(|info| info.connection().to_string())
+            .collect()
+    }
+
+    /// A handle to stop the server once it runs.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Also shuts down the server on SIGINT (Ctrl+C) or SIGTERM. A second signal exits the
+    /// process right away.
+    pub fn shutdown_on_signal(mut self) -> Self {
+        self.shutdown_on_signal = true;
+        self
+    }
+
+    /// Accepts connections until a shutdown is requested.
+    ///
+    /// The server then stops accepting connections and waits for active requests and running
+    /// [`Job`](crate::Job)s to finish, at most for the
+    /// [`shutdown_timeout`](crate::FrameworkBuilder::shutdown_timeout). Their responses are
+    /// sent with `Connection: close`, and idle keep-alive connections are closed right away.
+    pub async fn run(mut self) -> Result {
+        for address in self.addresses() {
+            tracing::info!(%address, "Listening");
+        }
+        let shutdown = self.shutdown.clone();
+        let shutdown_on_signal = self.shutdown_on_signal;
+        let stop = async move {
+            if shutdown_on_signal {
+                futures_lite::future::or(shutdown.wait(), wait_for_signal()).await;
+            } else {
+                shutdown.wait().await;
+            }
+            Ok(())
+        };
+        let redirect = async {
+            match &mut self.redirect {
+                Some(redirect) => redirect.accept().await,
+                None => futures_lite::future::pending().await,
+            }
+        };
+        let accept = futures_lite::future::or(self.listener.accept(), redirect);
+        futures_lite::future::or(accept, stop)
+            .await
+            .map_err(Error::Tide)?;
+        // Also stops a shutdown on signal from serving further requests on open connections
+        self.shutdown.shutdown();
+        // Closes the listeners, connections that are open keep being served
+        drop(self.listener);
+        drop(self.redirect);
+
+        tracing::info!(
+            active_requests = self.metrics.active_requests(),
+            running_jobs = self.jobs.running(),
+            open_connections = self.connections.count(),
+            "Shutting down"
+        );
+        let deadline = Instant::now() + self.shutdown_timeout;
+        while self.metrics.active_requests() > 0
+            || self.jobs.running() > 0
+            || self.connections.count() > 0
+        {
+            let finished = futures_lite::future::or(self.metrics.finished(), self.jobs.finished());
+            let closed = futures_lite::future::or(finished, self.connections.closed());
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if async_std::future::timeout(remaining, closed).await.is_err() {
+                tracing::warn!(
+                    active_requests = self.metrics.active_requests(),
+                    running_jobs = self.jobs.running(),
+                    open_connections = self.connections.count(),
+                    "Shutdown timed out"
+                );
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Redirects every request to the same URL on the HTTPS `port`.
+pub(crate) struct RedirectToHttps {
+    pub port: u16,
+}
+
+#[async_trait::async_trait]
+impl tide::Middleware<()> for RedirectToHttps {
+    async fn handle(&self, request: tide::Request<()>, _: tide::Next<'_, ()>) -> tide::Result {
+        let mut url = request.url().clone();
+        // Both only fail for URLs that can not be HTTP URLs in the first place
+        let _ = url.set_scheme("https");
+        let _ = url.set_port(Some(self.port).filter(|port| *port != 443));
+        Ok(tide::Response::builder(tide::StatusCode::PermanentRedirect)
+            .header("Location", url.as_str())
+            .build())
+    }
+}
+
+/// Stops a running [`Server`], can be cloned and sent to other tasks.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    // Nothing is ever sent, closing the channel wakes up every receiver
+    sender: Sender<()>,
+    receiver: Receiver<()>,
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        let (sender, receiver) = bounded(1);
+        Self { sender, receiver }
+    }
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.sender.close();
+    }
+
+    pub(crate) async fn wait(&self) {
+        let _ = self.receiver.recv().await;
+    }
+
+    pub(crate) fn is_shut_down(&self) -> bool {
+        self.receiver.is_closed()
+    }
+}
+
+/// Wakes up a task when something it waits for may have changed, e.g. a request finished.
+///
+/// A notification without a waiting task is kept for the next one, so none is missed between
+/// checking a condition and waiting for it to change.
+#[derive(Clone)]
+pub(crate) struct Notify {
+    sender: Sender<()>,
+    receiver: Receiver<()>,
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        let (sender, receiver) = bounded(1);
+        Self { sender, receiver }
+    }
+}
+
+impl Notify {
+    pub(crate) fn notify(&self) {
+        // Full means a notification is pending already
+        let _ = self.sender.try_send(());
+    }
+
+    pub(crate) async fn notified(&self) {
+        let _ = self.receiver.recv().await;
+    }
+}
+
+/// Completes on the first SIGINT or SIGTERM.
+async fn wait_for_signal() {
+    // A process only has one signal handler, so it is shared by every server. The sender is
+    // kept here, so the channel stays open if the handler can not be installed.
+    static SIGNAL: OnceLock<(Sender<()>, Receiver<()>)> = OnceLock::new();
+    let (_, receiver) = SIGNAL.get_or_init(|| {
+        let (sender, receiver) = bounded(1);
+        let handler_sender = sender.clone();
+        let result = ctrlc::set_handler(move || {
+            if !handler_sender.close() {
+                tracing::warn!("Received a second signal, exiting");
+                std::process::exit(130);
+            }
+        });
+        if let Err(e) = result {
+            tracing::error!(error = %e, "Could not install a handler for SIGINT and SIGTERM");
+        }
+        (sender, receiver)
+    });
+    let _ = receiver.recv().await;
+}
+
+/// A page that takes a while to construct, to test draining requests.
+#[cfg(test)]
+#[derive(crate::Page, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[page(path = "/slow")]
+struct Slow {
+    value: String,
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl crate::Constructor for Slow {
+    async fn construct(_: crate::Request<()>) -> Result<Self> {
+        async_std::task::sleep(Duration::from_millis(200)).await;
+        Ok(Self {
+            value: "done".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) async fn get(
+    mut stream: impl async_std::io::Read + async_std::io::Write + Unpin,
+    host: &str,
+    path: &str,
+) -> String {
+    use async_std::io::{ReadExt, WriteExt};
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[cfg(test)]
+pub(crate) async fn connect(address: &str) -> async_std::net::TcpStream {
+    let address = address.split("://").last().unwrap();
+    async_std::net::TcpStream::connect(address).await.unwrap()
+}
+
+/// Sends `requests` over one TLS 1.3 connection and returns their responses.
+///
+/// Like curl, the client sends the first request in the same write as the end of its
+/// handshake, so the server receives both at once.
+#[cfg(test)]
+pub(crate) fn tls13_requests(
+    address: &str,
+    roots: futures_rustls::rustls::RootCertStore,
+    requests: &[&str],
+) -> Vec<String> {
+    use futures_rustls::rustls::{self, ClientConnection, StreamOwned};
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let provider = rustls::crypto::ring::default_provider();
+    let config = rustls::ClientConfig::builder_with_provider(std::sync::Arc::new(provider))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+    let mut connection = ClientConnection::new(std::sync::Arc::new(config), server_name).unwrap();
+    let mut socket = std::net::TcpStream::connect(address.split("://").last().unwrap()).unwrap();
+    // Fails the test instead of hanging it
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    while connection.is_handshaking() {
+        while connection.wants_write() {
+            connection.write_tls(&mut socket).unwrap();
+        }
+        if connection.is_handshaking() {
+            connection.read_tls(&mut socket).unwrap();
+            connection.process_new_packets().unwrap();
+        }
+    }
+    // The Finished message is still queued, the first request goes out with it
+    connection
+        .writer()
+        .write_all(requests[0].as_bytes())
+        .unwrap();
+    let mut out = Vec::new();
+    while connection.wants_write() {
+        connection.write_tls(&mut out).unwrap();
+    }
+    socket.write_all(&out).unwrap();
+
+    let mut stream = BufReader::new(StreamOwned::new(connection, socket));
+    let mut responses = Vec::new();
+    for (i, request) in requests.iter().enumerate() {
+        if i > 0 {
+            stream.get_mut().write_all(request.as_bytes()).unwrap();
+        }
+        let mut response = String::new();
+        while !response.ends_with("\r\n\r\n") {
+            assert!(stream.read_line(&mut response).unwrap() > 0, "{}", response);
+        }
+        let length = response
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .map_or(0, |length| length.parse().unwrap());
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).unwrap();
+        response.push_str(std::str::from_utf8(&body).unwrap());
+        responses.push(response);
+    }
+    responses
+}
+
+#[test]
+fn test_bind_reports_addresses() {
+    async_std::task::block_on(async {
+        let config: crate::config::Config =
+            toml::from_str(r#"bind = ["127.0.0.1:0", "127.0.0.1:0"]"#).unwrap();
+        let server = crate::FrameworkBuilder::default()
+            .bind_config(&config)
+            .await
+            .unwrap();
+        let addresses = server.addresses();
+        assert_eq!(addresses.len(), 2, "{:?}", addresses);
+        for address in &addresses {
+            assert!(address.starts_with("http://127.0.0.1:"), "{}", address);
+            assert!(!address.ends_with(":0"), "{}", address);
+        }
+        assert_ne!(addresses[0], addresses[1]);
+    });
+}
+
+#[test]
+fn test_shutdown_drains_active_requests() {
+    async_std::task::block_on(async {
+        let server = crate::FrameworkBuilder::default()
+            .add_page::<Slow>()
+            .bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = server.addresses().remove(0);
+        let shutdown = server.shutdown_handle();
+        let running = async_std::task::spawn(server.run());
+
+        let request = async_std::task::spawn({
+            let address = address.clone();
+            async move { get(connect(&address).await, "localhost", "/slow.json").await }
+        });
+        // Wait until the request is being handled
+        async_std::task::sleep(Duration::from_millis(50)).await;
+        shutdown.shutdown();
+
+        running.await.unwrap();
+        let response = request.await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("\"value\":\"done\""), "{}", response);
+        assert!(
+            !response.contains("strict-transport-security"),
+            "{}",
+            response
+        );
+
+        // New connections are refused after the shutdown
+        let connect = async_std::net::TcpStream::connect(address.trim_start_matches("http://"));
+        assert!(connect.await.is_err());
+    });
+}
+
+#[test]
+fn test_shutdown_closes_idle_connections() {
+    use async_std::io::{ReadExt, WriteExt};
+
+    async_std::task::block_on(async {
+        let server = crate::FrameworkBuilder::default()
+            .add_page::<Slow>()
+            .shutdown_timeout(Duration::from_secs(10))
+            .bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = server.addresses().remove(0);
+        let shutdown = server.shutdown_handle();
+        let running = async_std::task::spawn(server.run());
+
+        // A keep-alive connection that waits for its next request
+        let mut idle = connect(&address).await;
+        idle.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = vec![0; 12];
+        idle.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, b"HTTP/1.1 200");
+
+        let request = async_std::task::spawn({
+            let address = address.clone();
+            async move {
+                let mut stream = connect(&address).await;
+                let request = "GET /slow.json HTTP/1.1\r\nHost: localhost\r\n\r\n";
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                response
+            }
+        });
+        async_std::task::sleep(Duration::from_millis(50)).await;
+        let started = Instant::now();
+        shutdown.shutdown();
+
+        // Both connections are closed by the server, without waiting for the timeout
+        let closed = async_std::future::timeout(Duration::from_secs(5), async {
+            idle.read_to_end(&mut response).await.unwrap();
+            running.await.unwrap();
+            request.await
+        });
+        let response = closed.await.expect("Connections were kept open");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("connection: close"), "{}", response);
+    });
+}
+
+#[test]
+fn test_tls_request_with_handshake() {
+    async_std::task::block_on(async {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let directory = std::env::temp_dir().join(format!("tls13-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (cert, key) = (directory.join("cert.pem"), directory.join("key.pem"));
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+
+        let server = crate::FrameworkBuilder::default()
+            .bind_tls("127.0.0.1:0", &cert, &key)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let https = server.addresses().remove(0);
+        let shutdown = server.shutdown_handle();
+        let running = async_std::task::spawn(server.run());
+
+        let mut roots = futures_rustls::rustls::RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let request = "GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let responses =
+            async_std::task::spawn_blocking(move || tls13_requests(&https, roots, &[request]))
+                .await;
+        assert!(responses[0].starts_with("HTTP/1.1 200"), "{}", responses[0]);
+
+        shutdown.shutdown();
+        running.await.unwrap();
+    });
+}
+
+#[test]
+fn test_tls() {
+    use futures_rustls::rustls;
+    use futures_rustls::TlsConnector;
+
+    async_std::task::block_on(async {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let directory = std::env::temp_dir().join(format!("tls-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (cert, key) = (directory.join("cert.pem"), directory.join("key.pem"));
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+
+        let server = crate::FrameworkBuilder::default()
+            .redirect_http("127.0.0.1:0")
+            .bind_tls("127.0.0.1:0", &cert, &key)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let addresses = server.addresses();
+        let [https, http] = &addresses[..] else {
+            panic!("{:?}", addresses)
+        };
+        assert!(https.starts_with("https://127.0.0.1:"), "{}", https);
+        assert!(http.starts_with("http://127.0.0.1:"), "{}", http);
+        let shutdown = server.shutdown_handle();
+        let running = async_std::task::spawn(server.run());
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let provider = rustls::crypto::ring::default_provider();
+        let config = rustls::ClientConfig::builder_with_provider(std::sync::Arc::new(provider))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let server_name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+        let stream = TlsConnector::from(std::sync::Arc::new(config))
+            .connect(server_name, connect(https).await)
+            .await
+            .unwrap();
+        let response = get(stream, "localhost", "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(
+            response.contains("strict-transport-security: max-age=31536000"),
+            "{}",
+            response
+        );
+
+        // Back-to-back requests on a TLS 1.3 keep-alive connection, the first one sent along
+        // with the end of the handshake
+        let keep_alive = "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let close = "GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let responses = async_std::task::spawn_blocking({
+            let https = https.clone();
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add(certified.cert.der().clone()).unwrap();
+            move || tls13_requests(&https, roots, &[keep_alive, keep_alive, close])
+        })
+        .await;
+        assert_eq!(responses.len(), 3);
+        for response in &responses {
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+            assert!(
+                response.contains("strict-transport-security"),
+                "{}",
+                response
+            );
+        }
+
+        let response = get(connect(http).await, "localhost:8080", "/vms?refresh=off").await;
+        let port = https.rsplit(':').next().unwrap();
+        assert!(response.starts_with("HTTP/1.1 308"), "{}", response);
+        assert!(
+            response.contains(&format!(
+                "location: https://localhost:{}/vms?refresh=off",
+                port
+            )),
+            "{}",
+            response
+        );
+
+        shutdown.shutdown();
+        running.await.unwrap();
+    });
+}
@@ -0,0 +1,190 @@
+//! Helpers used by `#[derive(Page)]` to render the initial state of a page on the server, so it
+//! is usable before (or without) javascript, and to map table rows to and from their ids.
+
+use crate::escape::escape_html;
+use crate::{Error, Result, TableRow};
+use serde_json::Value;
+
+/// Renders `value` the way script.js' `data-text` binding would, escaped for HTML.
+pub fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(str) => escape_html(str),
+        value => escape_html(&value.to_string()),
+    }
+}
+
+/// The rows of a `#[table]` field, or nothing if the field is not an array.
+pub fn rows(value: &Value) -> &[Value] {
+    match value {
+        Value::Array(rows) => rows,
+        _ => &[],
+    }
+}
+
+/// Adds the [`TableRow::id`] of every row to its serialized object as `_id`.
+pub fn insert_row_ids<'a, R: TableRow + 'a>(
+    value: &mut Value,
+    rows: impl IntoIterator<Item = &'a R>,
+) {
+    if let Value::Array(values) = value {
+        for (value, row) in values.iter_mut().zip(rows) {
+            if let Value::Object(object) = value {
+                object.insert("_id".to_string(), Value::String(row.id()));
+            }
+        }
+    }
+}
+
+/// Adds whether action `function` is enabled for every row to its serialized object, as
+/// `_enabled: { function: bool }`.
+pub fn insert_row_enabled<'a, R: 'a>(
+    value: &mut Value,
+    rows: impl IntoIterator<Item = &'a R>,
+    function: &str,
+    enabled: impl Fn(&R) -> bool,
+) {
+    if let Value::Array(values) = value {
+        for (value, row) in values.iter_mut().zip(rows) {
+            if let Value::Object(object) = value {
+                let actions = object
+                    .entry("_enabled")
+                    .or_insert_with(|| Value::Object(Default::default()));
+                if let Value::Object(actions) = actions {
+                    actions.insert(function.to_string(), Value::Bool(enabled(row)));
+                }
+            }
+        }
+    }
+}
+
+/// The ` hidden` attribute for the button of action `function` if it is disabled for `row`.
+pub fn hidden_unless_enabled(row: &Value, function: &str) -> &'static str {
+    if row["_enabled"][function] == Value::Bool(false) {
+        " hidden"
+    } else {
+        ""
+    }
+}
+
+/// The rows with the given [`TableRow::id`]s, in the order of the table.
+pub fn select_rows<'a, R: TableRow + 'a>(
+    rows: impl IntoIterator<Item = &'a R>,
+    ids: &[String],
+) -> Vec<R> {
+    rows.into_iter()
+        .filter(|row| ids.contains(&row.id()))
+        .cloned()
+        .collect()
+}
+
+/// The single row a per-row `#[action]` was invoked for.
+pub fn select_row<'a, R: TableRow + 'a>(
+    rows: impl IntoIterator<Item = &'a R>,
+    ids: &[String],
+) -> Result<R> {
+    let [id] = ids else {
+        return Err(Error::NotFound(format!(
+            "Expected the id of a single row, got {:?}",
+            ids
+        )));
+    };
+    rows.into_iter()
+        .find(|row| row.id() == *id)
+        .cloned()
+        .ok_or_else(|| Error::NotFound(format!("Row {:?} not found", id)))
+}
+
+/// Checks the `enabled_if` method of action `function` again before it is invoked, as the row
+/// may have changed since the page showed it.
+pub fn ensure_enabled(enabled: bool, function: &str, row: &impl TableRow) -> Result {
+    if enabled {
+        Ok(())
+    } else {
+        Err(Error::Conflict(format!(
+            "Action {:?} is not enabled for row {:?}",
+            function,
+            row.id()
+        )))
+    }
+}
+
+#[test]
+fn test_text() {
+    assert_eq!(text(&Value::Null), "");
+    assert_eq!(
+        text(&serde_json::json!("<b>infra</b>")),
+        "&lt;b&gt;infra&lt;/b&gt;"
+    );
+    assert_eq!(text(&serde_json::json!(5)), "5");
+    assert_eq!(text(&serde_json::json!(true)), "true");
+}
+
+#[test]
+fn test_insert_row_ids() {
+    #[derive(Clone)]
+    struct Row(&'static str);
+    impl TableRow for Row {
+        fn id(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    let rows = vec![Row("a"), Row("b")];
+    let mut value = serde_json::json!([{ "name": "first" }, { "name": "second" }]);
+    insert_row_ids(&mut value, &rows);
+    assert_eq!(
+        value,
+        serde_json::json!([{ "name": "first", "_id": "a" }, { "name": "second", "_id": "b" }])
+    );
+}
+
+#[test]
+fn test_insert_row_enabled() {
+    let rows = vec![1, 2, 3];
+    let mut value = serde_json::json!([{}, {}, {}]);
+    insert_row_enabled(&mut value, &rows, "even", |row| row % 2 == 0);
+    insert_row_enabled(&mut value, &rows, "odd", |row| row % 2 == 1);
+    assert_eq!(
+        value[1],
+        serde_json::json!({ "_enabled": { "even": true, "odd": false } })
+    );
+    assert_eq!(hidden_unless_enabled(&value[0], "even"), " hidden");
+    assert_eq!(hidden_unless_enabled(&value[1], "even"), "");
+    assert_eq!(hidden_unless_enabled(&value[1], "unknown"), "");
+}
+
+#[test]
+fn test_select_rows() {
+    #[derive(Clone)]
+    struct Row(&'static str);
+    impl TableRow for Row {
+        fn id(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    let rows = vec![Row("a"), Row("b"), Row("c")];
+    let ids = ["c".to_string(), "gone".to_string(), "a".to_string()];
+    let selected = select_rows(&rows, &ids);
+    assert_eq!(
+        selected.iter().map(|row| row.0).collect::<Vec<_>>(),
+        ["a", "c"]
+    );
+    assert!(select_rows(&rows, &[]).is_empty());
+
+    assert_eq!(select_row(&rows, &["b".to_string()]).unwrap().0, "b");
+    assert!(matches!(
+        select_row(&rows, &["gone".to_string()]),
+        Err(Error::NotFound(_))
+    ));
+    assert!(matches!(
+        select_row(&rows, &ids[..2]),
+        Err(Error::NotFound(_))
+    ));
+    assert!(ensure_enabled(true, "stop", &rows[0]).is_ok());
+    assert!(matches!(
+        ensure_enabled(false, "stop", &rows[0]),
+        Err(Error::Conflict(_))
+    ));
+}
@@ -0,0 +1,553 @@
+//! Tests pages and actions in-process, without binding a socket.
+//!
+//! ```ignore
+//! let client = TestClient::new(FrameworkBuilder::default().add_page::<Vms>())?;
+//! let response = client.action("/vms", "stop", &["1"]).await;
+//! assert_eq!(response.status(), 202);
+//! let job = client.wait_for_job(&response).await;
+//! assert_eq!(job.json()["state"], "succeeded");
+//! ```
+
+use crate::audit::{AuditEntry, MemorySink};
+use crate::{AuditSink, FrameworkBuilder, Page, Result};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tide::http::{Method, Request, Url};
+
+/// How long [`TestClient::wait_for_job`] waits for a job to finish.
+const JOB_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends requests straight to the routes of a [`FrameworkBuilder`].
+///
+/// Actions are recorded to a [`MemorySink`], unless the builder has its own
+/// [`audit_sink`](FrameworkBuilder::audit_sink).
+pub struct TestClient<T> {
+    server: tide::Server<T>,
+    audit_sink: Arc<dyn AuditSink>,
+    headers: Vec<(String, String)>,
+}
+
+impl<T: Clone + Send + Sync + 'static> TestClient<T> {
+    pub fn new(mut builder: FrameworkBuilder<T>) -> Result<Self> {
+        let audit_sink = builder
+            .audit_sink
+            .get_or_insert_with(|| Arc::new(MemorySink::default()))
+            .clone();
+        let (server, _, _) = builder.build(false)?;
+        Ok(Self {
+            server,
+            audit_sink,
+            headers: Vec::new(),
+        })
+    }
+
+    /// Sends `header` with every request.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Logs in with every request, see [`FrameworkBuilder::basic_auth`].
+    pub fn basic_auth(self, user: &str, password: &str) -> Self {
+        let auth = tide::http::auth::BasicAuth::new(user, password);
+        self.header("Authorization", auth.value().as_str())
+    }
+
+    pub async fn get(&self, path: &str) -> TestResponse {
+        self.send(self.request(Method::Get, path)).await
+    }
+
+    pub async fn post_json(&self, path: &str, json: &Value) -> TestResponse {
+        let mut request = self.request(Method::Post, path);
+        request.set_body(json.to_string());
+        request.set_content_type(tide::http::mime::JSON);
+        self.send(request).await
+    }
+
+    /// Invokes the action `function` of the page at `url`, for the rows with the
+    /// [`TableRow::id`](crate::TableRow::id)s `rows`.
+    pub async fn action(&self, url: &str, function: &str, rows: &[&str]) -> TestResponse {
+        let path = format!("{}/actions/{}", url, function);
+        self.post_json(&path, &serde_json::json!({ "rows": rows }))
+            .await
+    }
+
+    /// The model of page `P`, as served at its `.json` URL.
+    ///
+    /// # Panics
+    ///
+    /// If the page can not be constructed or its model not deserialized.
+    pub async fn model<P: Page<T>>(&self) -> P {
+        let response = self.get(&format!("{}.json", P::URL)).await;
+        assert_eq!(response.status(), 200, "{}", response.text());
+        serde_json::from_value(response.json())
+            .unwrap_or_else(|e| panic!("Invalid model of {}: {}", P::URL, e))
+    }
+
+    /// Polls the job started by the action of `response` until it is no longer running, and
+    /// returns its last status.
+    ///
+    /// # Panics
+    ///
+    /// If `response` did not start a job, or the job runs for longer than 10 seconds.
+    pub async fn wait_for_job(&self, response: &TestResponse) -> TestResponse {
+        let json = response.json();
+        let url = json["job"]
+            .as_str()
+            .unwrap_or_else(|| panic!("Response did not start a job: {}", json));
+        let start = Instant::now();
+        loop {
+            let status = self.get(url).await;
+            if status.json()["state"] != "running" {
+                return status;
+            }
+            assert!(start.elapsed() < JOB_TIMEOUT, "Job {} did not finish", url);
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// The audit log of every action invoked so far, newest first.
+    pub async fn audit_log(&self) -> Vec<AuditEntry> {
+        self.audit_sink
+            .entries(usize::MAX)
+            .await
+            .expect("Could not read the audit log")
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect()
+    }
+
+    /// A request for `path` with the headers of this client, to customize before
+    /// [`send`](Self::send)ing it.
+    pub fn request(&self, method: Method, path: &str) -> Request {
+        let url = Url::parse("http://localhost")
+            .and_then(|base| base.join(path))
+            .unwrap_or_else(|e| panic!("Invalid path {}: {}", path, e));
+        let mut request = Request::new(method, url);
+        for (name, value) in &self.headers {
+            request.insert_header(name.as_str(), value.as_str());
+        }
+        request
+    }
+
+    pub async fn send(&self, request: Request) -> TestResponse {
+        let mut response: tide::http::Response = self
+            .server
+            .respond(request)
+            .await
+            .expect("The framework always responds");
+        let body = response
+            .body_bytes()
+            .await
+            .expect("Could not read the response");
+        TestResponse { response, body }
+    }
+}
+
+/// A response of a [`TestClient`].
+pub struct TestResponse {
+    response: tide::http::Response,
+    body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn status(&self) -> u16 {
+        self.response.status() as u16
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.response.header(name).map(|values| values.as_str())
+    }
+
+    pub fn content_type(&self) -> Option<String> {
+        self.response
+            .content_type()
+            .map(|mime| mime.essence().to_string())
+    }
+
+    /// The body, e.g. the rendered HTML of a page.
+    ///
+    /// # Panics
+    ///
+    /// If the body is not UTF-8, use [`bytes`](Self::bytes) for binary responses.
+    pub fn text(&self) -> &str {
+        std::str::from_utf8(&self.body).expect("Response is not valid UTF-8")
+    }
+
+    /// The raw body, e.g. an exported workbook.
+    pub fn bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// # Panics
+    ///
+    /// If the body is not JSON.
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|e| panic!("Response is not JSON ({}): {}", e, self.text()))
+    }
+}
+
+#[cfg(test)]
+#[derive(crate::Page, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[page(path = "/counters")]
+struct Counters {
+    #[table(selectable, export = ["csv", "xlsx"])]
+    #[column(field = "name", header = "Name")]
+    #[column(field = "count", header = "Count")]
+    #[action(name = "Increment", fn = "increment", enabled_if = "is_small")]
+    #[bulk_action(name = "Reset", fn = "reset")]
+    #[bulk_action(name = "Reset slowly", fn = "reset_slowly")]
+    counters: Vec<Counter>,
+}
+
+#[cfg(test)]
+#[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+struct Counter {
+    name: String,
+    count: u32,
+}
+
+#[cfg(test)]
+impl Counter {
+    fn is_small(&self) -> bool {
+        self.count < 2
+    }
+}
+
+#[cfg(test)]
+impl crate::TableRow for Counter {
+    fn id(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl crate::Constructor for Counters {
+    async fn construct(_: crate::Request<()>) -> Result<Self> {
+        let counter = |name: &str, count| Counter {
+            name: name.to_string(),
+            count,
+        };
+        Ok(Self {
+            counters: vec![counter("a", 1), counter("b", 2)],
+        })
+    }
+}
+
+#[cfg(test)]
+impl Counters {
+    async fn increment(&mut self, _: crate::Request<()>, row: Counter) -> Result {
+        for counter in &mut self.counters {
+            if counter.name == row.name {
+                counter.count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    async fn reset(&mut self, _: crate::Request<()>, rows: Vec<Counter>) -> Result {
+        for counter in &mut self.counters {
+            if rows.iter().any(|row| row.name == counter.name) {
+                counter.count = 0;
+            }
+        }
+        Ok(())
+    }
+
+    async fn reset_slowly(
+        &mut self,
+        request: crate::Request<()>,
+        rows: Vec<Counter>,
+    ) -> Result<crate::Job> {
+        request.spawn_job("Reset", |job| async move {
+            job.log(format!("Resetting {} counters", rows.len()));
+            Ok(())
+        })
+    }
+}
+
+#[test]
+fn test_client() {
+    async_std::task::block_on(async {
+        let client = TestClient::new(FrameworkBuilder::default().add_page::<Counters>()).unwrap();
+
+        let page = client.get("/counters").await;
+        assert_eq!(page.status(), 200);
+        assert_eq!(page.content_type().as_deref(), Some("text/html"));
+        assert!(
+            page.text().contains("data-bulk-action=\"reset\""),
+            "{}",
+            page.text()
+        );
+
+        let json = client.get("/counters.json").await;
+        assert_eq!(json.json()["counters"][1]["count"], 2);
+        let model = client.model::<Counters>().await;
+        assert_eq!(model.counters.len(), 2);
+
+        let reset = client.action("/counters", "reset", &["b"]).await;
+        assert_eq!(reset.status(), 200);
+        assert_eq!(reset.json()["model"]["counters"][0]["count"], 1);
+        assert_eq!(reset.json()["model"]["counters"][1]["count"], 0);
+
+        let started = client
+            .action("/counters", "reset_slowly", &["a", "b"])
+            .await;
+        assert_eq!(started.status(), 202);
+        let job = client.wait_for_job(&started).await;
+        assert_eq!(job.json()["state"], "succeeded");
+        assert_eq!(job.json()["log"][0], "Resetting 2 counters");
+
+        let increment = client.action("/counters", "increment", &["a"]).await;
+        assert_eq!(increment.status(), 200, "{}", increment.text());
+        assert_eq!(increment.json()["model"]["counters"][0]["count"], 2);
+        // Disabled for rows that are not small, even if the page still showed the action
+        let disabled = client.action("/counters", "increment", &["b"]).await;
+        assert_eq!(disabled.status(), 409);
+        let gone = client.action("/counters", "increment", &["gone"]).await;
+        assert_eq!(gone.status(), 404);
+        let both = client.action("/counters", "increment", &["a", "b"]).await;
+        assert_eq!(both.status(), 404);
+
+        let missing = client.action("/counters", "missing", &[]).await;
+        assert_eq!(missing.status(), 404);
+        let not_json = client.send(client.request(Method::Post, "/counters/actions/reset"));
+        assert_eq!(not_json.await.status(), 415);
+
+        let actions: Vec<_> = client
+            .audit_log()
+            .await
+            .into_iter()
+            .map(|entry| entry.action)
+            .collect();
+        let expected = [
+            "missing",
+            "increment",
+            "increment",
+            "increment",
+            "increment",
+            "reset_slowly",
+            "reset_slowly",
+            "reset",
+        ];
+        assert_eq!(actions, expected);
+    });
+}
+
+#[test]
+fn test_client_basic_auth() {
+    async_std::task::block_on(async {
+        let builder = || {
+            FrameworkBuilder::default()
+                .add_page::<Counters>()
+                .basic_auth([("alice".to_string(), "secret".to_string())])
+        };
+        let anonymous = TestClient::new(builder()).unwrap();
+        let response = anonymous.get("/counters").await;
+        assert_eq!(response.status(), 401);
+        assert!(response.header("WWW-Authenticate").is_some());
+
+        let alice = TestClient::new(builder())
+            .unwrap()
+            .basic_auth("alice", "secret");
+        assert_eq!(alice.get("/counters").await.status(), 200);
+        alice.action("/counters", "reset", &["a"]).await;
+        assert_eq!(alice.audit_log().await[0].user.as_deref(), Some("alice"));
+
+        let wrong = TestClient::new(builder())
+            .unwrap()
+            .basic_auth("alice", "wrong");
+        assert_eq!(wrong.get("/counters").await.status(), 401);
+    });
+}
+
+/// Embeds [`Counters`], to test that components follow the refresh overrides of their page.
+#[cfg(test)]
+#[derive(crate::Page, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[page(path = "/overview", refresh = "1m")]
+struct Overview {
+    #[component]
+    counters: crate::Component<Counters>,
+}
+
+#[test]
+fn test_client_component_refresh() {
+    async_std::task::block_on(async {
+        let builder = FrameworkBuilder::default()
+            .add_page::<Counters>()
+            .add_page::<Overview>()
+            .refresh::<Counters>(Some(Duration::from_secs(10)));
+        let client = TestClient::new(builder).unwrap();
+        let refresh = |html: &str, url: &str| {
+            let attribute = format!("data-url=\"{}.json\" data-refresh=\"", url);
+            let start = html.find(&attribute).unwrap() + attribute.len();
+            html[start..start + html[start..].find('"').unwrap()].to_string()
+        };
+
+        let page = client.get("/overview").await;
+        assert_eq!(refresh(page.text(), "/overview"), "60000");
+        assert_eq!(refresh(page.text(), "/counters"), "10000");
+        let query = client.get("/overview?refresh=2s").await;
+        assert_eq!(refresh(query.text(), "/overview"), "2000");
+        assert_eq!(refresh(query.text(), "/counters"), "2000");
+        let off = client.get("/overview?refresh=off").await;
+        assert_eq!(refresh(off.text(), "/overview"), "");
+        assert_eq!(refresh(off.text(), "/counters"), "");
+    });
+}
+
+#[test]
+fn test_client_action_buttons() {
+    async_std::task::block_on(async {
+        let client = TestClient::new(FrameworkBuilder::default().add_page::<Counters>()).unwrap();
+        let page = client.get("/counters?host=b").await;
+        let values = |attribute: &str| -> Vec<String> {
+            let attribute = format!(" {}=\"", attribute);
+            page.text()
+                .split(attribute.as_str())
+                .skip(1)
+                .map(|rest| rest[..rest.find('"').unwrap()].to_string())
+                .collect()
+        };
+
+        // script.js posts to `<data-url without .json>/actions/<fn>` for every button, with the
+        // query of the page
+        let script = include_str!("../static/script.js");
+        assert!(script.contains("fetch(page.path + '/actions/' + fn + page.query, {"));
+        let url = values("data-url").remove(0);
+        let (path, query) = url.split_once('?').unwrap();
+        assert_eq!(query, "host=b");
+        let path = path.strip_suffix(".json").unwrap();
+        let mut buttons = values("data-action");
+        buttons.extend(values("data-bulk-action"));
+        buttons.sort();
+        buttons.dedup();
+        assert_eq!(buttons, ["increment", "reset", "reset_slowly"]);
+        for function in buttons {
+            let action = format!("{}/actions/{}?{}", path, function, query);
+            let rows = serde_json::json!({ "rows": ["a"] });
+            let response = client.post_json(&action, &rows).await;
+            assert!(
+                response.status() == 200 || response.status() == 202,
+                "{}: {} {}",
+                function,
+                response.status(),
+                response.text()
+            );
+        }
+    });
+}
+
+#[test]
+fn test_client_formats() {
+    async_std::task::block_on(async {
+        let client = TestClient::new(FrameworkBuilder::default().add_page::<Counters>()).unwrap();
+        let accept = |mime: &str| {
+            let mut request = client.request(Method::Get, "/counters");
+            request.insert_header("Accept", mime);
+            client.send(request)
+        };
+
+        let csv = client.get("/counters.csv").await;
+        assert_eq!(csv.status(), 200);
+        assert_eq!(csv.content_type().as_deref(), Some("text/csv"));
+        assert_eq!(csv.text(), "Name,Count\r\na,1\r\nb,2\r\n");
+        let ndjson = client.get("/counters.ndjson").await;
+        assert_eq!(
+            ndjson.content_type().as_deref(),
+            Some("application/x-ndjson")
+        );
+        let lines: Vec<Value> = ndjson
+            .text()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], serde_json::json!({ "name": "b", "count": 2 }));
+        assert_eq!(
+            client.get("/counters.csv?table=counters").await.status(),
+            200
+        );
+        let page = client.get("/counters").await;
+        assert!(
+            page.text()
+                .contains("<a href=\"/counters.xlsx?table=counters\" download>Excel</a>"),
+            "{}",
+            page.text()
+        );
+        // Reloads and exports keep the query of the page, but not its refresh interval
+        let filtered = client.get("/counters?host=b%26c&refresh=off").await;
+        assert!(
+            filtered
+                .text()
+                .contains("data-url=\"/counters.json?host=b%26c\""),
+            "{}",
+            filtered.text()
+        );
+        assert!(
+            filtered
+                .text()
+                .contains("<a href=\"/counters.csv?host=b%26c&amp;table=counters\" download>"),
+            "{}",
+            filtered.text()
+        );
+        let xlsx = client.get("/counters.xlsx?table=counters").await;
+        assert_eq!(xlsx.status(), 200);
+        assert_eq!(
+            xlsx.content_type().as_deref(),
+            Some("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+        );
+        assert!(xlsx.bytes().starts_with(b"PK"));
+        assert_eq!(
+            client.get("/counters.csv?table=missing").await.status(),
+            404
+        );
+
+        let json = accept("application/json").await;
+        assert_eq!(json.status(), 200);
+        assert_eq!(json.json()["counters"][0]["name"], "a");
+        assert_eq!(json.header("Vary"), Some("Accept"));
+        let csv = accept("text/html;q=0.5, text/csv").await;
+        assert_eq!(csv.status(), 200);
+        assert_eq!(csv.content_type().as_deref(), Some("text/csv"));
+        let ndjson = accept("application/x-ndjson").await;
+        assert_eq!(ndjson.status(), 200);
+        assert_eq!(
+            ndjson.content_type().as_deref(),
+            Some("application/x-ndjson")
+        );
+        for html in [
+            "text/html",
+            "TEXT/HTML; charset=utf-8",
+            "text/html,application/xhtml+xml,*/*;q=0.8",
+            "*/*",
+            "text/*",
+            "image/png, */*;q=0.1",
+        ] {
+            let response = accept(html).await;
+            assert_eq!(response.status(), 200, "{}", html);
+            assert_eq!(
+                response.content_type().as_deref(),
+                Some("text/html"),
+                "{}",
+                html
+            );
+            assert!(response.text().contains("page_"), "{}", html);
+        }
+        let json = accept("text/*;q=0.2, application/*;q=0.5").await;
+        assert_eq!(json.content_type().as_deref(), Some("application/json"));
+        let excluded = accept("text/html;q=0, */*").await;
+        assert_eq!(excluded.content_type().as_deref(), Some("application/json"));
+        assert_eq!(accept("image/png").await.status(), 406);
+        assert_eq!(accept("text/html;q=0").await.status(), 406);
+
+        assert_eq!(client.get("/counters.xml").await.status(), 404);
+        assert_eq!(
+            client.get("/missing.json").await.json()["error"],
+            "not found"
+        );
+    });
+}
@@ -1,18 +1,465 @@
-function enable_auto_reload(interval) {
-    setInterval(reload, interval)
+// The binding runtime for pages generated by `#[derive(Page)]`.
+//
+// It implements exactly the attributes the derive emits:
+// - `data-page`, `data-url` and `data-refresh` mark the root of a page (or embedded component),
+//   its JSON endpoint and its refresh interval in milliseconds. The initial model is read from
+//   the `<script type="application/json" id="{id}_model">` next to it.
+// - `data-text="field"` sets the text of the element to the value of `field`.
+// - `data-foreach="field"` renders the `<template>` referenced by `data-template` once for every
+//   row in `field`. Rows are matched by their `_id` (the `TableRow::id` added by the server,
+//   falling back to their index), so existing rows are updated in place instead of being
+//   re-created, and only cells whose value changed are touched.
+// - `data-action="fn"` invokes action `fn` of the page for the row the element is in.
+//   `data-enabled="fn"` hides it when the row's `_enabled.fn` is false, and `data-confirm`
+//   asks for confirmation first, with `{field}` replaced by the value of the row's `field`.
+// - In a `.framework-table`, `data-select` checkboxes select rows, `data-select-all` toggles all
+//   of them and `data-bulk-action="fn"` invokes action `fn` for the selected rows. Selections
+//   are kept across refreshes, as rows are matched by their `_id`. In their `data-confirm`,
+//   `{count}` is replaced by the number of selected rows.
+// - An action that starts a job gets a progress bar in the row it applies to (or in the toolbar),
+//   until the job at `/jobs/<id>.json` completes.
+
+// Upper bound of the delay between reloads while the server keeps failing
+const MAX_BACKOFF = 60000;
+// Fallback for removing rows when their animation does not run, e.g. without style.css
+const REMOVE_DELAY = 1000;
+// Interval at which the status of a running job is polled
+const JOB_POLL = 500;
+
+// Every page (and embedded component) on this document, by element id
+let pages = {};
+// The model of every table row, by its element
+let row_models = new WeakMap();
+
+// Pages are described by data attributes instead of inline scripts, so the page works under a
+// strict Content-Security-Policy
+document.addEventListener('DOMContentLoaded', function () {
+    for (let element of document.querySelectorAll('[data-page]')) {
+        load(element);
+    }
+});
+
+document.addEventListener('visibilitychange', function () {
+    for (let page of Object.values(pages)) {
+        if (document.hidden) {
+            clearTimeout(page.timer);
+        } else if (page.interval !== null && !page.paused) {
+            // Catch up on what we missed while the tab was in the background
+            reload(page);
+        }
+    }
+});
+
+function format(value) {
+    if (value === null || value === undefined) {
+        return '';
+    } else if (typeof value === 'object') {
+        return JSON.stringify(value);
+    } else {
+        return String(value);
+    }
+}
+
+// Binds `data` to the descendants of `root`. With `highlight`, elements whose text changed are
+// briefly highlighted.
+function bind(root, data, highlight) {
+    for (let element of root.children) {
+        bind_element(element, data, highlight);
+    }
+}
+
+function bind_element(element, data, highlight) {
+    if (element.hasAttribute('data-page')) {
+        // Embedded components bind their own model
+        return;
+    }
+    if (element.dataset.enabled !== undefined) {
+        let enabled = data !== null && data._enabled ? data._enabled[element.dataset.enabled] : undefined;
+        element.hidden = enabled === false;
+    }
+    if (element.dataset.text !== undefined) {
+        let text = format(data === null ? null : data[element.dataset.text]);
+        if (element.textContent !== text) {
+            element.textContent = text;
+            if (highlight) {
+                animate(element, 'framework-changed');
+            }
+        }
+    } else if (element.dataset.foreach !== undefined) {
+        let rows = data === null ? null : data[element.dataset.foreach];
+        update_rows(element, Array.isArray(rows) ? rows : []);
+        let table = element.closest('.framework-table');
+        if (table !== null) {
+            update_selection(table);
+        }
+    } else {
+        bind(element, data, highlight);
+    }
+}
+
+function animate(element, name) {
+    // Restart the animation if it is still running
+    element.classList.remove(name);
+    void element.offsetWidth;
+    element.classList.add(name);
+    element.addEventListener('animationend', function () {
+        element.classList.remove(name);
+    }, { once: true });
+}
+
+function is_removed(element) {
+    return element.classList.contains('framework-row-removed');
+}
+
+function next_row(element) {
+    while (element !== null && is_removed(element)) {
+        element = element.nextElementSibling;
+    }
+    return element;
 }
 
-async function reload() {
-    let response = await fetch(document.location.pathname + '.json');
-    let json = await response.json();
-    root.model(json);
+function remove_row(element) {
+    element.classList.add('framework-row-removed');
+    let remove = function () { element.remove(); };
+    element.addEventListener('animationend', remove, { once: true });
+    setTimeout(remove, REMOVE_DELAY);
 }
 
-root = {};
-function load(id, model) {
-    root = {
-        model: ko.observable()
+function row_key(row, index) {
+    if (row !== null && typeof row === 'object' && row._id !== undefined) {
+        return String(row._id);
+    }
+    return String(index);
+}
+
+function update_rows(container, rows) {
+    let template = document.getElementById(container.dataset.template);
+    let existing = new Map();
+    let index = 0;
+    for (let element of container.children) {
+        if (is_removed(element)) {
+            continue;
+        }
+        // Rows without a key are matched by index
+        existing.set(element.dataset.key !== undefined ? element.dataset.key : String(index), element);
+        index += 1;
+    }
+
+    let previous = null;
+    rows.forEach(function (row, index) {
+        let key = row_key(row, index);
+        let element = existing.get(key);
+        if (element === undefined) {
+            element = template.content.firstElementChild.cloneNode(true);
+            bind(element, row, false);
+            animate(element, 'framework-row-inserted');
+        } else {
+            existing.delete(key);
+            bind(element, row, true);
+        }
+        element.dataset.key = key;
+        row_models.set(element, row);
+
+        // Only move rows that are out of place, moving a row loses focus and selection
+        let expected = next_row(previous === null ? container.firstElementChild : previous.nextElementSibling);
+        if (expected !== element) {
+            container.insertBefore(element, expected);
+        }
+        previous = element;
+    });
+
+    for (let element of existing.values()) {
+        remove_row(element);
+    }
+}
+
+// The row checkboxes of `table`, without the rows that are being removed
+function row_checkboxes(table) {
+    return Array.from(table.querySelectorAll('tbody [data-select]'))
+        .filter(function (checkbox) { return !is_removed(checkbox.closest('tr')); });
+}
+
+// Syncs the select-all checkbox and the bulk action buttons with the selected rows
+function update_selection(table) {
+    let checkboxes = row_checkboxes(table);
+    let selected = checkboxes.filter(function (checkbox) { return checkbox.checked; }).length;
+    let select_all = table.querySelector('[data-select-all]');
+    if (select_all !== null) {
+        select_all.checked = selected > 0 && selected === checkboxes.length;
+        select_all.indeterminate = selected > 0 && selected < checkboxes.length;
+    }
+    for (let button of table.querySelectorAll('[data-bulk-action]')) {
+        button.disabled = selected === 0;
+    }
+}
+
+function selected_checkboxes(table) {
+    return row_checkboxes(table).filter(function (checkbox) { return checkbox.checked; });
+}
+
+// Invokes action `fn` of the page for the rows with the given keys, and shows the model the
+// server responds with. If the action started a job, its progress is shown in `cells`.
+async function invoke_action(page, fn, keys, cells) {
+    try {
+        // With the page's query, so the action runs on the page the user is looking at
+        let response = await fetch(page.path + '/actions/' + fn + page.query, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ rows: keys }),
+        });
+        if (!response.ok) {
+            throw new Error('server responded with ' + response.status + ' ' + response.statusText);
+        }
+        let result = await response.json();
+        update(page, result.model);
+        if (result.job !== null) {
+            track_job(page, result.job, cells);
+        }
+        return true;
+    } catch (e) {
+        page.toolbar.banner.hidden = false;
+        page.toolbar.banner.textContent = 'Action failed: ' + e.message;
+        return false;
+    }
+}
+
+// Shows a progress bar in every cell until the job at `url` completes, then reloads the page
+async function track_job(page, url, cells) {
+    if (cells.length === 0) {
+        cells = [page.toolbar.element];
+    }
+    let bars = cells.map(function (cell) {
+        let bar = document.createElement('progress');
+        bar.className = 'framework-progress';
+        bar.max = 1;
+        cell.append(bar);
+        let row = cell.closest('tr');
+        if (row !== null) {
+            row.classList.add('framework-busy');
+            row.setAttribute('aria-busy', 'true');
+        }
+        return bar;
+    });
+    try {
+        while (true) {
+            let response = await fetch(url);
+            if (!response.ok) {
+                throw new Error('server responded with ' + response.status + ' ' + response.statusText);
+            }
+            let job = await response.json();
+            for (let bar of bars) {
+                if (job.progress === null) {
+                    // Without a value the bar is shown as indeterminate
+                    bar.removeAttribute('value');
+                } else {
+                    bar.value = job.progress;
+                }
+                bar.title = job.name + '\n' + job.log.join('\n');
+            }
+            if (job.state === 'failed') {
+                throw new Error(job.error);
+            } else if (job.state !== 'running') {
+                break;
+            }
+            await new Promise(function (resolve) { setTimeout(resolve, JOB_POLL); });
+        }
+    } catch (e) {
+        page.toolbar.banner.hidden = false;
+        page.toolbar.banner.textContent = 'Job failed: ' + e.message;
+    } finally {
+        for (let bar of bars) {
+            let row = bar.closest('tr');
+            if (row !== null) {
+                row.classList.remove('framework-busy');
+                row.removeAttribute('aria-busy');
+            }
+            bar.remove();
+        }
+        reload(page);
+    }
+}
+
+// Asks for confirmation if `element` has a `data-confirm` message, filling in its placeholders
+// from `values`
+function confirmed(element, values) {
+    if (element.dataset.confirm === undefined) {
+        return true;
+    }
+    let message = element.dataset.confirm.replace(/\{(\w+)\}/g, function (placeholder, field) {
+        return values !== null && values !== undefined && field in values ? format(values[field]) : placeholder;
+    });
+    return window.confirm(message);
+}
+
+async function invoke_row_action(page, button) {
+    let row = button.closest('[data-key]');
+    if (!confirmed(button, row === null ? null : row_models.get(row))) {
+        return;
+    }
+    let keys = row === null ? [] : [row.dataset.key];
+    // Prevents invoking the action twice by double clicking
+    button.disabled = true;
+    try {
+        await invoke_action(page, button.dataset.action, keys, [button.closest('td')]);
+    } finally {
+        button.disabled = false;
+    }
+}
+
+async function invoke_bulk_action(page, button) {
+    let table = button.closest('.framework-table');
+    let checkboxes = selected_checkboxes(table);
+    if (!confirmed(button, { count: checkboxes.length })) {
+        return;
+    }
+    let keys = checkboxes.map(function (checkbox) { return checkbox.closest('[data-key]').dataset.key; });
+    let cells = checkboxes.map(function (checkbox) { return checkbox.closest('td'); });
+    if (await invoke_action(page, button.dataset.bulkAction, keys, cells)) {
+        for (let checkbox of row_checkboxes(table)) {
+            checkbox.checked = false;
+        }
+        update_selection(table);
+    }
+}
+
+function update(page, model) {
+    page.model = model;
+    bind(page.element, page.model, false);
+    page.failures = 0;
+    page.toolbar.banner.hidden = true;
+    page.toolbar.updated.textContent = 'Last updated ' + new Date().toLocaleTimeString();
+}
+
+function schedule_reload(page) {
+    clearTimeout(page.timer);
+    if (page.interval === null || page.paused || document.hidden) {
+        return;
+    }
+    let delay = Math.min(page.interval * Math.pow(2, page.failures), Math.max(page.interval, MAX_BACKOFF));
+    page.timer = setTimeout(function () { reload(page); }, delay);
+}
+
+async function reload(page) {
+    // A slow server should not pile up requests
+    if (page.in_flight) {
+        return;
+    }
+    page.in_flight = true;
+    page.toolbar.refresh.disabled = true;
+    try {
+        // Pages that refresh identify themselves, so the server can count its live clients
+        let headers = {};
+        if (page.interval !== null) {
+            headers['X-Framework-Client'] = page.client;
+            headers['X-Framework-Refresh'] = String(page.interval);
+        }
+        let response = await fetch(page.url, { headers });
+        if (!response.ok) {
+            throw new Error('server responded with ' + response.status + ' ' + response.statusText);
+        }
+        update(page, await response.json());
+    } catch (e) {
+        page.failures += 1;
+        page.toolbar.banner.hidden = false;
+        page.toolbar.banner.textContent = 'Connection lost: ' + e.message + '. Retrying...';
+    } finally {
+        page.in_flight = false;
+        page.toolbar.refresh.disabled = false;
+        schedule_reload(page);
+    }
+}
+
+function toggle_pause(page) {
+    page.paused = !page.paused;
+    page.toolbar.pause.textContent = page.paused ? 'Resume' : 'Pause';
+    if (page.paused) {
+        clearTimeout(page.timer);
+    } else {
+        reload(page);
+    }
+}
+
+function create_toolbar(page) {
+    let toolbar = document.createElement('div');
+    toolbar.className = 'framework-toolbar';
+
+    let updated = document.createElement('span');
+    updated.textContent = 'Last updated ' + new Date().toLocaleTimeString();
+
+    let pause = document.createElement('button');
+    pause.type = 'button';
+    pause.textContent = 'Pause';
+    pause.hidden = page.interval === null;
+    pause.addEventListener('click', function () { toggle_pause(page); });
+
+    let refresh_now = document.createElement('button');
+    refresh_now.type = 'button';
+    refresh_now.textContent = 'Refresh';
+    refresh_now.addEventListener('click', function () { reload(page); });
+
+    let banner = document.createElement('div');
+    banner.className = 'framework-banner';
+    banner.setAttribute('role', 'alert');
+    banner.hidden = true;
+
+    toolbar.append(updated, ' ', pause, ' ', refresh_now, banner);
+    page.element.parentNode.insertBefore(toolbar, page.element);
+    return { element: toolbar, updated, pause, refresh: refresh_now, banner };
+}
+
+function load(element) {
+    let url = new URL(element.dataset.url, location.href);
+    let page = {
+        element: element,
+        model: JSON.parse(document.getElementById(element.id + '_model').textContent),
+        url: element.dataset.url,
+        path: url.pathname.replace(/\.json$/, ''),
+        // The query the model is constructed from, empty for components
+        query: url.search,
+        interval: element.dataset.refresh ? parseInt(element.dataset.refresh, 10) : null,
+        client: Math.random().toString(36).slice(2),
+        timer: null,
+        paused: false,
+        in_flight: false,
+        failures: 0,
     };
-    root.model(model);
-    ko.applyBindings(root, document.getElementById(id));
-}
\ No newline at end of file
+    pages[element.id] = page;
+    page.toolbar = create_toolbar(page);
+
+    // Events inside an embedded component are handled by that component
+    let own = function (target) {
+        return target !== null && target.closest('[data-page]') === element;
+    };
+    element.addEventListener('click', function (event) {
+        let action = event.target.closest('[data-action]');
+        let bulk_action = event.target.closest('[data-bulk-action]');
+        if (own(action)) {
+            event.preventDefault();
+            invoke_row_action(page, action);
+        } else if (own(bulk_action)) {
+            event.preventDefault();
+            invoke_bulk_action(page, bulk_action);
+        }
+    });
+    element.addEventListener('change', function (event) {
+        if (!own(event.target) || !event.target.matches('[data-select], [data-select-all]')) {
+            return;
+        }
+        let table = event.target.closest('.framework-table');
+        if (event.target.matches('[data-select-all]')) {
+            for (let checkbox of row_checkboxes(table)) {
+                checkbox.checked = event.target.checked;
+            }
+        }
+        update_selection(table);
+    });
+
+    if (page.model === null) {
+        // Components are rendered without a model and fetch their own
+        reload(page);
+    } else {
+        // The server already rendered the model, this only takes over the existing rows
+        bind(element, page.model, false);
+        schedule_reload(page);
+    }
+}
@@ -1,23 +1,69 @@
 mod vms;
 
 use framework::prelude::*;
+use framework::Config;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use std::time::Duration;
 
 #[async_std::main]
 async fn main() {
-    FrameworkBuilder::default()
+    // Settings come from `web.toml` and `WEB_*` environment variables, e.g. `WEB_BIND=0.0.0.0:80`
+    let mut config = Config::load("web.toml").unwrap();
+    // Who turned which VM off has to survive a restart, so the audit log is kept in a file
+    config
+        .audit
+        .path
+        .get_or_insert_with(|| "audit.jsonl".into());
+
+    // Logs a line per request, configure with e.g. `WEB_LOG=framework=debug` or `RUST_LOG`
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&config.log)),
+        )
+        .init();
+
+    FrameworkBuilder::with_state(AppState::default())
+        .add_page::<Dashboard>()
         .add_page::<Uptime>()
         .add_page::<vms::Vms>()
-        .run("localhost:8080")
+        .run_config(&config)
         .await
         .unwrap();
 }
 
-#[derive(Page, Serialize, Deserialize)]
+/// The state every page is constructed with.
+#[derive(Clone)]
+pub struct AppState {
+    pub virsh: Arc<dyn vms::Virsh>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self {
+            virsh: Arc::new(vms::SystemVirsh),
+        }
+    }
+}
+
+#[derive(Default, Page, Serialize, Deserialize, JsonSchema)]
+#[page(path = "/dashboard")]
+pub struct Dashboard {
+    #[component]
+    uptime: Component<Uptime>,
+    #[component(refresh = "10s")]
+    vms: Component<vms::Vms>,
+}
+
+#[derive(Page, Serialize, Deserialize, JsonSchema)]
 #[page(path = "/uptime", refresh = "1s")]
 pub struct Uptime {
-    #[serde(serialize_with = "serialize_duration_as_string")]
+    #[serde(
+        serialize_with = "serialize_duration_as_string",
+        deserialize_with = "deserialize_duration_from_string"
+    )]
+    #[schemars(with = "String")]
     uptime: Duration,
 }
 
@@ -31,12 +77,46 @@ where
     format!("{}s", duration.as_secs()).serialize(s)
 }
 
+fn deserialize_duration_from_string<'de, D>(d: D) -> std::result::Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let duration = String::deserialize(d)?;
+    framework::duration::parse_duration(&duration).map_err(serde::de::Error::custom)
+}
+
 #[async_trait]
-impl Constructor for Uptime {
-    async fn construct(_: Request<()>) -> Result<Self> {
+impl<T: Send + Sync + 'static> Constructor<T> for Uptime {
+    async fn construct(_: Request<T>) -> Result<Self> {
         match uptime_lib::get() {
             Ok(uptime) => Ok(Self { uptime }),
             Err(e) => Err(e.into()),
         }
     }
 }
+
+#[test]
+fn test_uptime_page() {
+    use framework::testing::TestClient;
+    async_std::task::block_on(async {
+        let client = TestClient::new(FrameworkBuilder::default().add_page::<Uptime>()).unwrap();
+        let page = client.get("/uptime").await;
+        assert_eq!(page.status(), 200, "{}", page.text());
+        assert!(page.text().contains("<label"), "{}", page.text());
+
+        let json = client.get("/uptime.json").await.json();
+        assert!(json["uptime"].as_str().unwrap().ends_with('s'), "{}", json);
+        assert_eq!(client.get("/missing").await.status(), 404);
+    });
+}
+
+#[test]
+fn test_uptime_round_trip() {
+    let uptime = Uptime {
+        uptime: Duration::from_secs(90),
+    };
+    let json = serde_json::to_value(&uptime).unwrap();
+    assert_eq!(json["uptime"], "90s");
+    let parsed: Uptime = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed.uptime, uptime.uptime);
+}
@@ -1,60 +1,62 @@
+use crate::AppState;
 use framework::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::process::Output;
 
-#[derive(Page, Serialize, Deserialize)]
-#[page(path = "/vms", refresh = "5s")]
+#[derive(Page, Serialize, Deserialize, JsonSchema)]
+#[page(path = "/vms", refresh = "5s", cache = "2s", state = "AppState")]
 pub struct Vms {
-    #[table]
+    #[table(selectable, export = ["csv", "xlsx"])]
     #[column(field = "name", header = "Name")]
     #[column(field = "state", header = "State")]
-    // #[action(name = "on", action = "turn_on")]
-    // #[action(name = "off", action = "turn_off")]
+    #[action(name = "on", fn = "turn_on", enabled_if = "is_shut_off")]
+    #[action(
+        name = "off",
+        fn = "turn_off",
+        enabled_if = "is_running",
+        confirm = "Really shut down {name}?"
+    )]
+    #[bulk_action(name = "Start", fn = "start")]
+    #[bulk_action(name = "Stop", fn = "stop", confirm = "Really shut down {count} VMs?")]
     pub vms: Vec<Vm>,
 }
 
 #[async_trait]
-impl Constructor for Vms {
-    async fn construct(_: Request<()>) -> Result<Self> {
+impl Constructor<AppState> for Vms {
+    async fn construct(request: Request<AppState>) -> Result<Self> {
         let mut result = Self { vms: Vec::new() };
-        result.load()?;
+        result.load(request.state().virsh.as_ref())?;
         Ok(result)
     }
 }
 
 impl Vms {
-    #[allow(dead_code)]
-    pub async fn turn_on(&mut self, _: Request<()>, row: Vm) -> Result {
-        std::process::Command::new("virsh")
-            .args(&["start", &row.name])
-            .stdout(std::process::Stdio::piped())
-            .output()?;
-        self.load()?;
-        Ok(())
+    pub async fn turn_on(&mut self, request: Request<AppState>, row: Vm) -> Result<Job> {
+        virsh_job(&request, "start", vec![row])
     }
 
-    #[allow(dead_code)]
-    pub async fn turn_off(&mut self, _: Request<()>, row: Vm) -> Result {
-        std::process::Command::new("virsh")
-            .args(&["shutdown", &row.name])
-            .stdout(std::process::Stdio::piped())
-            .output()?;
-        self.load()?;
-        Ok(())
+    pub async fn turn_off(&mut self, request: Request<AppState>, row: Vm) -> Result<Job> {
+        virsh_job(&request, "shutdown", vec![row])
+    }
+
+    pub async fn start(&mut self, request: Request<AppState>, rows: Vec<Vm>) -> Result<Job> {
+        virsh_job(&request, "start", rows)
+    }
+
+    pub async fn stop(&mut self, request: Request<AppState>, rows: Vec<Vm>) -> Result<Job> {
+        virsh_job(&request, "shutdown", rows)
     }
 
     #[cfg(not(windows))]
-    fn load(&mut self) -> Result {
-        let result = std::process::Command::new("virsh")
-            .args(&["list", "--all"])
-            .stdout(std::process::Stdio::piped())
-            .output()?;
+    fn load(&mut self, virsh: &dyn Virsh) -> Result {
+        let result = virsh.run(&["list", "--all"])?;
         let stdout = String::from_utf8_lossy(&result.stdout);
         *self = Self::parse_str(&stdout)?;
         Ok(())
     }
 
     #[cfg(windows)]
-    fn load(&mut self) -> Result {
+    fn load(&mut self, _: &dyn Virsh) -> Result {
         let input = r#" Id   Name           State
 -------------------------------
  1    infra          running
@@ -88,6 +90,81 @@ impl Vms {
     }
 }
 
+/// Runs `virsh`, which lists the VMs and changes their state. Part of the [`AppState`], so
+/// tests can replace it and never touch the VMs of the machine they run on.
+pub trait Virsh: Send + Sync {
+    fn run(&self, args: &[&str]) -> std::io::Result<Output>;
+}
+
+/// The `virsh` command of the machine the app runs on.
+pub struct SystemVirsh;
+
+impl Virsh for SystemVirsh {
+    fn run(&self, args: &[&str]) -> std::io::Result<Output> {
+        std::process::Command::new("virsh").args(args).output()
+    }
+}
+
+/// Runs `virsh <command> <name>` for every row in the background, as it can take a while.
+fn virsh_job(request: &Request<AppState>, command: &'static str, rows: Vec<Vm>) -> Result<Job> {
+    let virsh = request.state().virsh.clone();
+    request.spawn_job(format!("virsh {}", command), move |job| async move {
+        for (index, row) in rows.iter().enumerate() {
+            job.log(format!("virsh {} {}", command, row.name));
+            let name = row.name.clone();
+            let virsh = virsh.clone();
+            let output =
+                async_std::task::spawn_blocking(move || virsh.run(&[command, &name])).await?;
+            job.log(String::from_utf8_lossy(&output.stdout).trim());
+            if !output.status.success() {
+                return Err(format!(
+                    "virsh {} {} failed: {}",
+                    command,
+                    row.name,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+                .into());
+            }
+            job.progress((index + 1) as f64 / rows.len() as f64);
+        }
+        Ok(())
+    })
+}
+
+/// Lists three VMs, and echoes the arguments of every other command.
+#[cfg(test)]
+struct FakeVirsh;
+
+#[cfg(test)]
+impl Virsh for FakeVirsh {
+    fn run(&self, args: &[&str]) -> std::io::Result<Output> {
+        let stdout = match args {
+            ["list", "--all"] => r#" Id   Name           State
+-------------------------------
+ 1    infra          running
+ -    trangar-dev    shut off
+ -    translucence   shut off
+"#
+            .to_string(),
+            args => args.join(" "),
+        };
+        Ok(Output {
+            status: Default::default(),
+            stdout: stdout.into_bytes(),
+            stderr: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+fn test_client() -> framework::testing::TestClient<AppState> {
+    let state = AppState {
+        virsh: std::sync::Arc::new(FakeVirsh),
+    };
+    let builder = FrameworkBuilder::with_state(state).add_page::<Vms>();
+    framework::testing::TestClient::new(builder).unwrap()
+}
+
 #[test]
 fn test_vms_parse_str() {
     let input = r#" Id   Name           State
@@ -109,20 +186,151 @@ fn test_vms_parse_str() {
     assert!(vms.vms[0].id() != vms.vms[2].id());
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[test]
+fn test_vms_html_renders_rows_on_the_server() {
+    let vms = Vms::parse_str(
+        r#" Id   Name           State
+-------------------------------
+ 1    infra          running
+ -    trangar-dev    shut off
+"#,
+    )
+    .expect("Could not parse input");
+    let html = vms.html(&framework::RenderContext::default());
+    assert!(
+        html.contains("<td data-text=\"name\">infra</td>"),
+        "{}",
+        html
+    );
+    assert!(
+        html.contains("<td data-text=\"state\">shut off</td>"),
+        "{}",
+        html
+    );
+    // The row template is rendered once, without values
+    assert!(
+        html.contains("<template id=\"page_Vms_vms\"><tr><td><input type=\"checkbox\" data-select"),
+        "{}",
+        html
+    );
+    assert!(html.contains("data-select-all"), "{}", html);
+    // One cell with the actions per row, only showing those enabled for its state
+    assert!(
+        html.contains(
+            "<td data-text=\"state\">running</td><td>\
+             <button type=\"button\" data-action=\"turn_on\" data-enabled=\"turn_on\" hidden>on</button>\
+             <button type=\"button\" data-action=\"turn_off\" \
+             data-confirm=\"Really shut down {name}?\" data-enabled=\"turn_off\">off</button>\
+             </td></tr>"
+        ),
+        "{}",
+        html
+    );
+    assert!(
+        html.contains(
+            "<button type=\"button\" data-bulk-action=\"stop\" \
+             data-confirm=\"Really shut down {count} VMs?\" disabled>Stop</button>"
+        ),
+        "{}",
+        html
+    );
+}
+
+#[test]
+fn test_vms_html_escapes_hostile_names() {
+    let vms = Vms {
+        vms: vec![Vm {
+            idx: 0,
+            name: "</script><script>alert(1)</script>".to_string(),
+            state: "<img src=x onerror=alert(2)>".to_string(),
+        }],
+    };
+    let html = vms.html(&framework::RenderContext::default());
+    assert!(!html.contains("<script>alert"), "{}", html);
+    assert!(!html.contains("<img"), "{}", html);
+    // Only the JSON data island, there are no inline scripts
+    assert_eq!(html.matches("<script").count(), 1, "{}", html);
+    assert_eq!(html.matches("</script>").count(), 1, "{}", html);
+}
+
+#[test]
+fn test_vms_actions_check_the_state_of_their_row() {
+    async_std::task::block_on(async {
+        let client = test_client();
+        let model = client.get("/vms.json").await.json();
+        let id = |name: &str| {
+            let vms = model["vms"].as_array().unwrap();
+            let vm = vms.iter().find(|vm| vm["name"] == name).unwrap();
+            vm["_id"].as_str().unwrap().to_string()
+        };
+        assert_eq!(model["vms"][0]["_enabled"]["turn_off"], true);
+        assert_eq!(model["vms"][0]["_enabled"]["turn_on"], false);
+
+        // `infra` is running and `trangar-dev` is shut off
+        let running = client.action("/vms", "turn_on", &[&id("infra")]).await;
+        assert_eq!(running.status(), 409, "{}", running.text());
+        let shut_off = client
+            .action("/vms", "turn_off", &[&id("trangar-dev")])
+            .await;
+        assert_eq!(shut_off.status(), 409, "{}", shut_off.text());
+        let missing = client.action("/vms", "turn_on", &["missing"]).await;
+        assert_eq!(missing.status(), 404, "{}", missing.text());
+    });
+}
+
+#[test]
+fn test_vms_actions_run_virsh_as_jobs() {
+    async_std::task::block_on(async {
+        let client = test_client();
+        let model = client.get("/vms.json").await.json();
+        let ids: Vec<_> = model["vms"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|vm| vm["_id"].as_str().unwrap())
+            .collect();
+
+        // `trangar-dev` is shut off
+        let started = client.action("/vms", "turn_on", &[ids[1]]).await;
+        assert_eq!(started.status(), 202, "{}", started.text());
+        let job = client.wait_for_job(&started).await.json();
+        assert_eq!(job["name"], "virsh start");
+        assert_eq!(job["state"], "succeeded", "{}", job);
+        assert_eq!(job["log"][0], "virsh start trangar-dev");
+        assert_eq!(job["log"][1], "start trangar-dev");
+        assert_eq!(job["progress"], 1.0);
+
+        let stopped = client.action("/vms", "stop", &[ids[0], ids[2]]).await;
+        assert_eq!(stopped.status(), 202, "{}", stopped.text());
+        let job = client.wait_for_job(&stopped).await.json();
+        assert_eq!(job["state"], "succeeded", "{}", job);
+        assert_eq!(job["log"][2], "virsh shutdown translucence");
+    });
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Vm {
     idx: usize,
     pub name: String,
     pub state: String,
 }
 
+impl Vm {
+    pub fn is_running(&self) -> bool {
+        self.state == "running"
+    }
+
+    pub fn is_shut_off(&self) -> bool {
+        self.state == "shut off"
+    }
+}
+
 impl TableRow for Vm {
     fn id(&self) -> String {
+        // Only the name identifies a VM, so a change in state updates the existing row
         use std::hash::{Hash, Hasher};
         let mut hasher = std::collections::hash_map::DefaultHasher::new();
         self.name.hash(&mut hasher);
-        self.state.hash(&mut hasher);
-        self.idx.hash(&mut hasher);
         hasher.finish().to_string()
     }
 }